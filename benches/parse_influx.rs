@@ -1,12 +1,51 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
 
-use influx_parser::{gen_line, parse_int, parse_tape, parse_tape_avx2};
+use influx_parser::{gen_line, parse_float, Backend, Parser, parse_int, parse_tape, parse_tape_avx2, parse_timestamp};
 
 fn parse_int_bench(c: &mut Criterion) {
     let value = String::from("64i");
     c.bench_function("Parse 64i", |b| b.iter(|| parse_int(black_box(&value))));
 }
 
+// Seconds, milliseconds and nanoseconds since the epoch
+const TIMESTAMPS: [&str; 3] = ["1700000000", "1700000000123", "1700000000123456789"];
+
+fn parse_int_digits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_int");
+    for timestamp in TIMESTAMPS {
+	let value = format!("{timestamp}i");
+	group.bench_with_input(BenchmarkId::from_parameter(timestamp.len()), &value, |b, value| {
+	    b.iter(|| parse_int(black_box(value)));
+	});
+    }
+}
+
+fn parse_timestamp_digits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_timestamp");
+    for timestamp in TIMESTAMPS {
+	group.bench_with_input(BenchmarkId::from_parameter(timestamp.len()), timestamp, |b, timestamp| {
+	    b.iter(|| parse_timestamp(black_box(timestamp)));
+	});
+	// std baseline, not the byte at a time fold the decoders replaced
+	group.bench_with_input(BenchmarkId::new("std", timestamp.len()), timestamp, |b, timestamp| {
+	    b.iter(|| black_box(timestamp).parse::<i64>());
+	});
+    }
+}
+
+fn parse_float_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_float");
+    // Typical metrics first, then one that has to take the slow path
+    for value in ["0.64", "-12.5", "1.5e3", "98.7654321", "3.141592653589793", "1.7976931348623157e308"] {
+	group.bench_with_input(BenchmarkId::new("parse_float", value), value, |b, value| {
+	    b.iter(|| parse_float(black_box(value)));
+	});
+	group.bench_with_input(BenchmarkId::new("std", value), value, |b, value| {
+	    b.iter(|| black_box(value).parse::<f64>());
+	});
+    }
+}
+
 fn parse_10k_lines(c: &mut Criterion) {
     let mut res: Vec<String> = Vec::new();
 
@@ -31,13 +70,27 @@ fn parse_influx(c: &mut Criterion) {
 
 	group.throughput(Throughput::Bytes(line.len() as u64));
 	group.bench_with_input(BenchmarkId::from_parameter(size), &line, |b, line| {
-	    b.iter(|| parse_tape(black_box(&line)));
+	    b.iter(|| parse_tape(black_box(line)));
 	});
 
 	res.clear();
     }
 }
 
+/// Stage 1 on its own, for every backend the CPU supports.
+fn stage1(c: &mut Criterion) {
+    let line: String = (0..10000).map(|_| gen_line()).collect();
+
+    let mut group = c.benchmark_group("stage1");
+    group.throughput(Throughput::Bytes(line.len() as u64));
+    for backend in Backend::ALL.into_iter().filter(|backend| backend.is_supported()) {
+	let lookup = backend.lookup();
+	group.bench_with_input(BenchmarkId::from_parameter(backend.name()), &line, |b, line| {
+	    b.iter(|| lookup(black_box(line)));
+	});
+    }
+}
+
 fn parse_influx_avx2(c: &mut Criterion) {
     let mut res: Vec<String> = Vec::new();
 
@@ -50,12 +103,45 @@ fn parse_influx_avx2(c: &mut Criterion) {
 
 	group.throughput(Throughput::Bytes(line.len() as u64));
 	group.bench_with_input(BenchmarkId::from_parameter(size), &line, |b, line| {
-	    b.iter(|| parse_tape_avx2(black_box(&line)));
+	    b.iter(|| parse_tape_avx2(black_box(line)));
 	});
 
 	res.clear();
     }
 }
 
-criterion_group!(benches, parse_int_bench, parse_influx, parse_influx_avx2);
+/// The 1M line input of `parse_influx` on 1, 2, 4, ... threads, up to as
+/// many as the machine has.
+fn parse_parallel(c: &mut Criterion) {
+    let line: String = (0..1000000).map(|_| gen_line()).collect();
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    let threads = (0..).map(|power| 1 << power).take_while(|&threads| threads < cpus).chain([cpus]);
+
+    let mut group = c.benchmark_group("parse_parallel");
+    group.throughput(Throughput::Bytes(line.len() as u64));
+    group.sample_size(10);
+    for threads in threads {
+	let parser = Parser::new().threads(threads);
+	group.bench_with_input(BenchmarkId::from_parameter(threads), &line, |b, line| {
+	    b.iter(|| parser.parse(black_box(line)));
+	});
+    }
+}
+
+/// Parsing bytes with the fused UTF-8 check against validating them in a
+/// pass of its own, and against input that is already a `str`.
+fn parse_bytes(c: &mut Criterion) {
+    let line: String = (0..10000).map(|_| gen_line()).collect();
+    let parser = Parser::new();
+
+    let mut group = c.benchmark_group("parse_bytes");
+    group.throughput(Throughput::Bytes(line.len() as u64));
+    group.bench_function("str", |b| b.iter(|| parser.parse(black_box(&line))));
+    group.bench_function("from_utf8", |b| {
+	b.iter(|| parser.parse(std::str::from_utf8(black_box(line.as_bytes())).unwrap()))
+    });
+    group.bench_function("fused", |b| b.iter(|| parser.parse_bytes(black_box(line.as_bytes()))));
+}
+
+criterion_group!(benches, parse_int_bench, parse_int_digits, parse_timestamp_digits, parse_float_bench, parse_10k_lines, parse_influx, parse_influx_avx2, stage1, parse_parallel, parse_bytes);
 criterion_main!(benches);
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::stream::LineBuffer;
+use crate::{iter_points, OwnedPoint, StreamError};
+
+/// Parses line protocol from a tokio [`AsyncRead`], such as a socket, one
+/// point at a time.
+///
+/// Reads are buffered and classified as they come in, just like in
+/// [`StreamParser`](crate::StreamParser), and partial lines wait for the next
+/// read. The stream ends after the first error, with error positions relative
+/// to the whole stream.
+///
+/// ```
+/// use influx_parser::PointStream;
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+/// use futures_core::Stream;
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let input: &[u8] = b"cpu usage=0.5\nmem free=7i\n";
+/// let mut points = PointStream::new(input);
+/// while let Some(point) = poll_fn(|cx| Pin::new(&mut points).poll_next(cx)).await {
+///     println!("{}", point.unwrap().point().measurement);
+/// }
+/// # });
+/// ```
+pub struct PointStream<R> {
+    reader: R,
+    lines: LineBuffer,
+    // Parsed from the last batch, not yielded yet
+    pending: VecDeque<Result<OwnedPoint, StreamError>>,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> PointStream<R> {
+    pub fn new(reader: R) -> Self {
+        PointStream {
+            reader,
+            lines: LineBuffer::new(),
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// How many bytes to ask the reader for at a time, 64 KiB by default.
+    pub fn read_size(mut self, read_size: usize) -> Self {
+        self.lines.read_size = read_size.max(1);
+        self
+    }
+
+    /// The longest line that is buffered before giving up with
+    /// [`StreamError::LineTooLong`], 1 MiB by default.
+    pub fn max_line_len(mut self, max_line_len: usize) -> Self {
+        self.lines.max_line_len = max_line_len;
+        self
+    }
+
+    /// Parse the complete lines buffered so far into `pending`.
+    fn parse_batch(&mut self) -> Result<(), StreamError> {
+        let (offset, lines) = self.lines.position();
+        let Some(batch) = self.lines.batch()? else {
+            self.done = true;
+            return Ok(());
+        };
+
+        for point in iter_points(batch) {
+            match point {
+                Ok(point) => self.pending.push_back(Ok(point.into_owned())),
+                Err(mut err) => {
+                    err.offset += offset;
+                    err.line += lines;
+                    self.pending.push_back(Err(StreamError::Parse(err)));
+                    self.done = true;
+                }
+            }
+        }
+        // The points own their nodes, so the lines can go right away
+        self.lines.release();
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for PointStream<R> {
+    type Item = Result<OwnedPoint, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(point) = this.pending.pop_front() {
+                return Poll::Ready(Some(point));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            match this.lines.needs_input() {
+                Ok(true) => {
+                    let mut buf = ReadBuf::new(this.lines.spare());
+                    match ready!(Pin::new(&mut this.reader).poll_read(cx, &mut buf)) {
+                        Ok(()) => {
+                            let read = buf.filled().len();
+                            this.lines.filled(read);
+                        }
+                        Err(err) => this.fail(err.into()),
+                    }
+                }
+                Ok(false) => {
+                    if let Err(err) = this.parse_batch() {
+                        this.fail(err);
+                    }
+                }
+                Err(err) => this.fail(err),
+            }
+        }
+    }
+}
+
+impl<R> PointStream<R> {
+    /// End the stream with `err`.
+    fn fail(&mut self, err: StreamError) {
+        self.pending.push_back(Err(err));
+        self.done = true;
+    }
+}
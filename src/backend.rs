@@ -0,0 +1,251 @@
+use std::sync::OnceLock;
+
+use crate::scanner::{self, Scalar};
+use crate::ScanState;
+
+/// Environment variable that forces a [`Backend`], see [`Backend::active`].
+pub const BACKEND_ENV_VAR: &str = "INFLUX_PARSER_BACKEND";
+
+/// A stage 1 implementation, i.e. the scanner that finds the structural bytes
+/// of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// One byte at a time, runs anywhere.
+    Scalar,
+    /// 64 bytes at a time in plain `u64` arithmetic, runs anywhere, see
+    /// [`shuffle_lookup_swar`](crate::shuffle_lookup_swar).
+    Swar,
+    /// 16 bytes at a time, see [`shuffle_lookup`](crate::shuffle_lookup).
+    Sse41,
+    /// 32 bytes at a time, see
+    /// [`shuffle_lookup_avx2`](crate::shuffle_lookup_avx2).
+    Avx2,
+    /// 64 bytes at a time, see
+    /// [`shuffle_lookup_avx512`](crate::shuffle_lookup_avx512).
+    Avx512,
+}
+
+/// The active backend together with its stage 1 functions, resolved once so
+/// that the entry points call straight through them.
+struct Active {
+    backend: Backend,
+    scan: ScanFn,
+    checked_scan: ScanFn,
+    classify: ClassifyFn,
+}
+
+static ACTIVE: OnceLock<Active> = OnceLock::new();
+
+impl Backend {
+    pub const ALL: [Backend; 5] = [
+        Backend::Scalar,
+        Backend::Swar,
+        Backend::Sse41,
+        Backend::Avx2,
+        Backend::Avx512,
+    ];
+
+    /// Whether the CPU this runs on can use the backend.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Scalar | Backend::Swar => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse41 => is_x86_feature_detected!("sse4.1"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => is_x86_feature_detected!("avx512bw"),
+            #[cfg(not(target_arch = "x86_64"))]
+            Backend::Sse41 | Backend::Avx2 | Backend::Avx512 => false,
+        }
+    }
+
+    /// The fastest backend the CPU supports.
+    pub fn detect() -> Backend {
+        [Backend::Avx512, Backend::Avx2, Backend::Sse41]
+            .into_iter()
+            .find(|backend| backend.is_supported())
+            .unwrap_or(Backend::Swar)
+    }
+
+    /// The backend used by [`parse`](crate::parse) and the other entry points
+    /// that do not name one. Picked once per process: the one named by
+    /// `INFLUX_PARSER_BACKEND` (`scalar`, `swar`, `sse4.1`, `avx2` or
+    /// `avx512`) if set, the fastest supported one otherwise. Its stage 1
+    /// functions are resolved at the same time, so the entry points call them
+    /// without dispatching again.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the environment variable names an unknown backend or one
+    /// the CPU does not support.
+    pub fn active() -> Backend {
+        Backend::resolved().backend
+    }
+
+    fn resolved() -> &'static Active {
+        ACTIVE.get_or_init(|| {
+            let backend = match std::env::var(BACKEND_ENV_VAR) {
+                Ok(name) => {
+                    let backend = Backend::from_name(&name).unwrap_or_else(|| {
+                        panic!(
+                            "unknown {BACKEND_ENV_VAR} {name:?}, \
+                             expected scalar, swar, sse4.1, avx2 or avx512"
+                        )
+                    });
+                    assert!(
+                        backend.is_supported(),
+                        "{BACKEND_ENV_VAR}={name} is not supported by this CPU"
+                    );
+                    backend
+                }
+                Err(_) => Backend::detect(),
+            };
+            Active {
+                backend,
+                scan: backend.scanner(),
+                checked_scan: backend.checked_scanner(),
+                classify: backend.classifier(),
+            }
+        })
+    }
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "scalar" => Some(Backend::Scalar),
+            "swar" => Some(Backend::Swar),
+            "sse4.1" | "sse41" => Some(Backend::Sse41),
+            "avx2" => Some(Backend::Avx2),
+            "avx512" => Some(Backend::Avx512),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Scalar => "scalar",
+            Backend::Swar => "swar",
+            Backend::Sse41 => "sse4.1",
+            Backend::Avx2 => "avx2",
+            Backend::Avx512 => "avx512",
+        }
+    }
+
+    /// The stage 1 function of the backend.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the CPU does not support the backend, and when the input
+    /// is too long for `u32` offsets (4 GiB). [`Parser`](crate::Parser) takes
+    /// inputs of any length.
+    pub fn lookup(self) -> fn(&str) -> Vec<u32> {
+        self.assert_supported();
+        match self {
+            Backend::Scalar => shuffle_lookup_scalar,
+            Backend::Swar => crate::shuffle_lookup_swar,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse41 => |record| unsafe { crate::shuffle_lookup(record) },
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => |record| unsafe { crate::shuffle_lookup_avx2(record) },
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => |record| unsafe { crate::shuffle_lookup_avx512(record) },
+            #[cfg(not(target_arch = "x86_64"))]
+            Backend::Sse41 | Backend::Avx2 | Backend::Avx512 => unreachable!(),
+        }
+    }
+
+    /// The stage 1 function of the backend for one segment of a longer
+    /// input, see [`scanner::scan`].
+    ///
+    /// # Panics
+    ///
+    /// Panics when the CPU does not support the backend.
+    pub(crate) fn scanner(self) -> ScanFn {
+        self.scan_fn::<false>()
+    }
+
+    /// Like [`scanner`](Self::scanner), but validating UTF-8 in the same
+    /// pass. Only for whole inputs, not segments.
+    pub(crate) fn checked_scanner(self) -> ScanFn {
+        self.scan_fn::<true>()
+    }
+
+    fn scan_fn<const CHECK: bool>(self) -> ScanFn {
+        self.assert_supported();
+        match self {
+            Backend::Scalar => |bytes, state, index| unsafe {
+                scanner::scan::<Scalar<64>, CHECK>(bytes, state, index)
+            },
+            Backend::Swar => |bytes, state, index| unsafe {
+                scanner::scan::<scanner::Swar, CHECK>(bytes, state, index)
+            },
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse41 => {
+                |bytes, state, index| unsafe { scanner::scan_sse41::<CHECK>(bytes, state, index) }
+            }
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => {
+                |bytes, state, index| unsafe { scanner::scan_avx2::<CHECK>(bytes, state, index) }
+            }
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => {
+                |bytes, state, index| unsafe { scanner::scan_avx512::<CHECK>(bytes, state, index) }
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            Backend::Sse41 | Backend::Avx2 | Backend::Avx512 => unreachable!(),
+        }
+    }
+
+    /// The 16 byte chunk classifier of the backend. SSE4.1 is the widest one,
+    /// so the AVX backends share it.
+    fn classifier(self) -> ClassifyFn {
+        self.assert_supported();
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse41 | Backend::Avx2 | Backend::Avx512 => {
+                |chunk, state| unsafe { crate::classify_sse41(chunk, state) }
+            }
+            _ => |chunk, state| unsafe { scanner::classify::<Scalar<16>>(chunk, state) },
+        }
+    }
+
+    fn assert_supported(self) {
+        assert!(
+            self.is_supported(),
+            "the {} backend is not supported by this CPU",
+            self.name()
+        );
+    }
+}
+
+/// Appends the structural offsets of a segment to the index, carrying the
+/// scan state over from the previous segment.
+pub(crate) type ScanFn = fn(&[u8], &mut ScanState, &mut Vec<u32>);
+
+/// Portable variant of [`shuffle_lookup`](crate::shuffle_lookup), finding the
+/// same structural bytes one byte at a time.
+///
+/// # Panics
+///
+/// Panics when `record` is too long for `u32` offsets (4 GiB).
+pub fn shuffle_lookup_scalar(record: &str) -> Vec<u32> {
+    unsafe { scanner::lookup::<Scalar<64>>(record) }
+}
+
+/// [`Backend::scanner`] of the [active backend](Backend::active).
+pub(crate) fn active_scanner() -> ScanFn {
+    Backend::resolved().scan
+}
+
+/// [`Backend::checked_scanner`] of the [active backend](Backend::active).
+pub(crate) fn active_checked_scanner() -> ScanFn {
+    Backend::resolved().checked_scan
+}
+
+/// Classifies one 16 byte chunk, returning a bitmask of its structural bytes.
+pub(crate) type ClassifyFn = fn(&[u8; 16], &mut ScanState) -> u64;
+
+/// The chunk classifier of the [active backend](Backend::active).
+pub(crate) fn active_classifier() -> ClassifyFn {
+    Backend::resolved().classify
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::str::Utf8Error;
+
+/// The longest snippet of input kept in a [`ParseError`], in bytes.
+const MAX_SNIPPET_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A line starts with a separator instead of a measurement name.
+    EmptyMeasurement,
+    /// A tag or field key is empty.
+    EmptyKey,
+    /// A tag value is empty.
+    EmptyTagValue,
+    /// A tag or field key is not followed by `=` and a value.
+    MissingValue,
+    /// The line has a measurement but no fields.
+    MissingFieldSet,
+    /// A separator where the line protocol does not allow one, e.g. `=` in a
+    /// measurement name or anything after the timestamp.
+    UnexpectedSeparator(char),
+    /// An `i` suffixed field value that is not a valid `i64`.
+    InvalidInteger,
+    /// A `u` suffixed field value that is not a valid `u64`.
+    InvalidUnsigned,
+    /// An unsuffixed field value that is not a valid finite float.
+    InvalidFloat,
+    /// A field value that does not have the shape of any field type.
+    InvalidFieldValue,
+    /// A timestamp that is not a valid integer.
+    InvalidTimestamp,
+    /// A timestamp that does not fit an `i64` once converted to the output
+    /// precision.
+    TimestampOutOfRange,
+    /// Byte input that is not UTF-8.
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::EmptyMeasurement => write!(f, "empty measurement"),
+            ParseErrorKind::EmptyKey => write!(f, "empty key"),
+            ParseErrorKind::EmptyTagValue => write!(f, "empty tag value"),
+            ParseErrorKind::MissingValue => write!(f, "key without a value"),
+            ParseErrorKind::MissingFieldSet => write!(f, "line without a field set"),
+            ParseErrorKind::UnexpectedSeparator(ch) => write!(f, "unexpected {ch:?}"),
+            ParseErrorKind::InvalidInteger => write!(f, "invalid integer field value"),
+            ParseErrorKind::InvalidUnsigned => write!(f, "invalid unsigned field value"),
+            ParseErrorKind::InvalidFloat => write!(f, "invalid float field value"),
+            ParseErrorKind::InvalidFieldValue => write!(f, "invalid field value"),
+            ParseErrorKind::InvalidTimestamp => write!(f, "invalid timestamp"),
+            ParseErrorKind::TimestampOutOfRange => write!(f, "timestamp out of range"),
+            ParseErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+        }
+    }
+}
+
+/// A malformed line, with enough context to point the user at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset of the error in the input.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    /// The offending part of the input, cut off after a few dozen bytes.
+    pub snippet: String,
+}
+
+impl ParseError {
+    /// Build an error for `input[start..end]`. An empty span gets the rest of
+    /// the line as its snippet instead. `line` is the 1-based line number of
+    /// `line_start`, a line start at or before `start`; only the input from
+    /// there on is counted, so callers reporting many errors keep track of
+    /// both as they go rather than have every error count from the start.
+    pub(crate) fn new(
+        kind: ParseErrorKind,
+        input: &str,
+        line: usize,
+        line_start: usize,
+        start: usize,
+        end: usize,
+    ) -> Self {
+        let (line, line_start) = line_of(input, line, line_start, start);
+        let column = input[line_start..start].chars().count() + 1;
+
+        let end = if start == end {
+            let rest = &input.as_bytes()[start..input.len().min(start + MAX_SNIPPET_LEN)];
+            rest.iter()
+                .position(|&c| c == b'\n')
+                .map_or(start + rest.len(), |idx| start + idx)
+        } else {
+            end
+        };
+        let mut snippet_end = end.min(start + MAX_SNIPPET_LEN);
+        while !input.is_char_boundary(snippet_end) {
+            snippet_end -= 1;
+        }
+
+        ParseError {
+            kind,
+            offset: start,
+            line,
+            column,
+            snippet: input[start..snippet_end].to_string(),
+        }
+    }
+
+    /// Build an error for the first invalid byte of `input`, with the line
+    /// and column counted in the valid part before it. The snippet shows the
+    /// invalid bytes lossily.
+    pub(crate) fn invalid_utf8(input: &[u8], err: Utf8Error) -> Self {
+        let start = err.valid_up_to();
+        let valid = std::str::from_utf8(&input[..start]).expect("valid up to here");
+        let end = err.error_len().map_or(input.len(), |len| start + len);
+        ParseError {
+            snippet: String::from_utf8_lossy(&input[start..end]).into_owned(),
+            ..ParseError::new(ParseErrorKind::InvalidUtf8, valid, 1, 0, start, start)
+        }
+    }
+}
+
+/// The 1-based line number and start of the line `offset` is on, counting
+/// on from `line` starting at `line_start`, at or before `offset`.
+pub(crate) fn line_of(
+    input: &str,
+    line: usize,
+    line_start: usize,
+    offset: usize,
+) -> (usize, usize) {
+    let bytes = &input.as_bytes()[line_start..offset];
+    match bytes.iter().rposition(|&c| c == b'\n') {
+        Some(last) => {
+            let newlines = bytes[..last].iter().filter(|&&c| c == b'\n').count() + 1;
+            (line + newlines, line_start + last + 1)
+        }
+        None => (line, line_start),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}: {:?}",
+            self.kind, self.line, self.column, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Errors from [`StreamParser`](crate::StreamParser) and
+/// [`parse_file`](crate::parse_file).
+#[derive(Debug)]
+pub enum StreamError {
+    Io(std::io::Error),
+    /// A line grew past the configured maximum without a newline.
+    LineTooLong {
+        offset: u64,
+        max_line_len: usize,
+    },
+    /// The input is not UTF-8, `offset` is the first invalid byte.
+    InvalidUtf8 {
+        offset: u64,
+    },
+    Parse(ParseError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(err) => write!(f, "read failed: {err}"),
+            StreamError::LineTooLong {
+                offset,
+                max_line_len,
+            } => write!(
+                f,
+                "line at byte {offset} is longer than {max_line_len} bytes"
+            ),
+            StreamError::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 at byte {offset}"),
+            StreamError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(err) => Some(err),
+            StreamError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StreamError {
+    fn from(err: std::io::Error) -> Self {
+        StreamError::Io(err)
+    }
+}
+
+impl From<ParseError> for StreamError {
+    fn from(err: ParseError) -> Self {
+        StreamError::Parse(err)
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::{iter_points, parse_points, ParseError, PointIter, Points, StreamError};
+
+/// Memory map the line protocol file at `path` and check that it is UTF-8.
+///
+/// Nothing is copied: the points borrow their measurements, keys and values
+/// straight from the mapping, so they cannot outlive the returned
+/// [`MappedFile`].
+///
+/// # Safety
+///
+/// The file must not be modified or truncated, by this process or any other,
+/// for as long as the [`MappedFile`] lives. The mapping is read as a `&str`
+/// that was checked once, so a change to the file is undefined behaviour, not
+/// just wrong results or a `SIGBUS`.
+///
+/// ```no_run
+/// use influx_parser::parse_file;
+///
+/// // SAFETY: nothing writes to the backfill while it is loaded
+/// let file = unsafe { parse_file("backfill.lp")? };
+/// for point in file.points() {
+///     let point = point?;
+///     println!("{}", point.point().measurement);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub unsafe fn parse_file(path: impl AsRef<Path>) -> Result<MappedFile, StreamError> {
+    let file = File::open(path)?;
+    let map = unsafe { Mmap::map(&file)? };
+    #[cfg(unix)]
+    map.advise(memmap2::Advice::Sequential)?;
+
+    if let Err(err) = std::str::from_utf8(&map) {
+        return Err(StreamError::InvalidUtf8 {
+            offset: err.valid_up_to() as u64,
+        });
+    }
+    Ok(MappedFile { map })
+}
+
+/// A memory mapped line protocol file, see [`parse_file`].
+#[derive(Debug)]
+pub struct MappedFile {
+    // Checked to be UTF-8 when mapped
+    map: Mmap,
+}
+
+impl MappedFile {
+    pub fn as_str(&self) -> &str {
+        // SAFETY: checked when mapped, and the caller of parse_file promised
+        // that the file does not change
+        unsafe { std::str::from_utf8_unchecked(&self.map) }
+    }
+
+    /// Lazily parse the file one point at a time, see [`PointIter`]. This is
+    /// the way to go for files too large to hold all of their nodes in memory.
+    pub fn points(&self) -> PointIter<'_> {
+        iter_points(self.as_str())
+    }
+
+    /// Parse the whole file at once, see [`parse_points`].
+    pub fn parse(&self) -> Result<Points<'_>, ParseError> {
+        parse_points(self.as_str())
+    }
+}
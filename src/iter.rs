@@ -0,0 +1,113 @@
+use crate::backend::{active_scanner, ScanFn};
+use crate::{ParseError, PointBuf, ScanState, TapeBuilder};
+
+/// Bytes classified each time the offset buffer runs dry.
+const BLOCK_SIZE: usize = 64;
+
+/// Lazily parses points, classifying the input one block at a time.
+///
+/// Unlike [`parse_points`](crate::parse_points) this never holds more than a
+/// block worth of structural offsets and the nodes of one point, so memory
+/// stays bounded however large the input is. Iteration ends after the first
+/// error.
+///
+/// ```
+/// use influx_parser::PointIter;
+///
+/// let input = "cpu usage=0.5\nmem free=7i\nbad\nmem free=8i\n";
+/// let mut points = PointIter::new(input);
+/// assert_eq!(points.next().unwrap().unwrap().point().measurement, "cpu");
+/// assert_eq!(points.next().unwrap().unwrap().point().measurement, "mem");
+/// assert!(points.next().unwrap().is_err());
+/// assert!(points.next().is_none());
+/// ```
+pub struct PointIter<'a> {
+    input: &'a str,
+    tape: TapeBuilder<'a>,
+    scan: ScanFn,
+    state: ScanState,
+    // Bytes classified so far
+    scanned: usize,
+    // Relative to the start of the last block
+    offsets: Vec<u32>,
+    block_start: usize,
+    // Next offset in `offsets` to feed to the tape
+    next: usize,
+    done: bool,
+}
+
+impl<'a> PointIter<'a> {
+    pub fn new(input: &'a str) -> Self {
+        PointIter {
+            input,
+            // Looking for escapes up front would mean a pass over the whole
+            // input, the unescape functions check each item instead.
+            tape: TapeBuilder::new(input, 0, true),
+            scan: active_scanner(),
+            state: ScanState::default(),
+            scanned: 0,
+            offsets: Vec::with_capacity(BLOCK_SIZE),
+            block_start: 0,
+            next: 0,
+            done: false,
+        }
+    }
+
+    /// Classify the next block in place, straight from the input.
+    fn scan_block(&mut self) {
+        let bytes = self.input.as_bytes();
+        let end = (self.scanned + BLOCK_SIZE).min(bytes.len());
+
+        self.offsets.clear();
+        self.next = 0;
+        self.block_start = self.scanned;
+        (self.scan)(
+            &bytes[self.scanned..end],
+            &mut self.state,
+            &mut self.offsets,
+        );
+        self.scanned = end;
+    }
+
+    fn next_offset(&mut self) -> Option<usize> {
+        loop {
+            if let Some(&offset) = self.offsets.get(self.next) {
+                self.next += 1;
+                let offset = self.block_start + offset as usize;
+                // The end of the input is fed to the tape separately, so the
+                // zero padding is not reported
+                if offset < self.input.len() {
+                    return Some(offset);
+                }
+                continue;
+            }
+            if self.scanned >= self.input.len() {
+                return None;
+            }
+            self.scan_block();
+        }
+    }
+}
+
+impl<'a> Iterator for PointIter<'a> {
+    type Item = Result<PointBuf<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            // Close the last line with a virtual newline at the end
+            let offset = self.next_offset().unwrap_or_else(|| {
+                self.done = true;
+                self.input.len()
+            });
+
+            if let Err(err) = self.tape.push(offset) {
+                self.done = true;
+                return Some(Err(err));
+            }
+            if self.tape.is_newline(offset) && !self.tape.items.is_empty() {
+                return Some(Ok(PointBuf::from_line(self.tape.take_line())));
+            }
+        }
+        None
+    }
+}
@@ -1,27 +1,255 @@
 use rand::distributions::{Alphanumeric, DistString};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use rand::Rng;
 
-pub fn parse_int(string_ref: &str) -> u64 {
-    // Can take a shortcut here
-    // if string_ref.len() - 1 == 8 {
-    // 	todo!();
-    // } else {
-       	// https://rust-malaysia.github.io/code/2020/07/11/faster-integer-parsing.html
-	let data = unsafe {string_ref.get_unchecked(0..string_ref.len() - 1)};
-	data.bytes().fold(0, |a, c| a * 10 + (c & 0x0f) as u64)
-    // }
+#[cfg(feature = "async")]
+mod async_stream;
+mod backend;
+mod error;
+mod file;
+mod iter;
+mod number;
+mod parallel;
+mod point;
+mod precision;
+mod scanner;
+pub mod reference;
+mod stream;
+mod utf8;
+mod writer;
+
+use backend::ScanFn;
+use error::line_of;
+#[cfg(feature = "async")]
+pub use async_stream::PointStream;
+pub use backend::{shuffle_lookup_scalar, Backend, BACKEND_ENV_VAR};
+pub use error::{ParseError, ParseErrorKind, StreamError};
+pub use file::{parse_file, MappedFile};
+pub use iter::PointIter;
+pub use point::{OwnedPoint, Point, PointBuf, Points};
+pub use precision::Precision;
+pub use stream::StreamParser;
+pub use writer::LineWriter;
+
+/// Parse a signed integer field value, dropping the trailing `i` suffix.
+/// Returns `None` on anything but digits and on overflow.
+pub fn parse_int(string_ref: &str) -> Option<i64> {
+    match string_ref.as_bytes().first() {
+	Some(b'-') => {
+	    let digits = unsafe {string_ref.get_unchecked(1..string_ref.len())};
+	    0i64.checked_sub_unsigned(parse_uint(digits)?)
+	},
+	_ => i64::try_from(parse_uint(string_ref)?).ok(),
+    }
 }
 
+/// Parse an unsigned integer, dropping the trailing suffix byte (`u` for
+/// unsigned field values). Returns `None` on anything but digits and on
+/// overflow.
+pub fn parse_uint(string_ref: &str) -> Option<u64> {
+    // https://rust-malaysia.github.io/code/2020/07/11/faster-integer-parsing.html
+    let (_, data) = string_ref.as_bytes().split_last()?;
+    number::parse_digits(data)
+}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Parse a timestamp, a plain integer without any suffix. Negative values,
+/// i.e. times before the epoch, are allowed. Returns `None` on anything but
+/// digits and on overflow.
+pub fn parse_timestamp(string_ref: &str) -> Option<i64> {
+    match string_ref.as_bytes().split_first()? {
+	(b'-', digits) => 0i64.checked_sub_unsigned(number::parse_digits(digits)?),
+	_ => i64::try_from(number::parse_digits(string_ref.as_bytes())?).ok(),
+    }
+}
+
+/// Parse a boolean field value. The line protocol accepts `t`, `T`, `true`,
+/// `True` and `TRUE`, and the same spellings of false.
+pub fn parse_bool(string_ref: &str) -> Option<bool> {
+    match string_ref {
+	"t" | "T" | "true" | "True" | "TRUE" => Some(true),
+	"f" | "F" | "false" | "False" | "FALSE" => Some(false),
+	_ => None,
+    }
+}
+
+/// Parse a line protocol float, `[-]digits[.digits][(e|E)[+|-]digits]`.
+///
+/// Unlike `str::parse::<f64>` this rejects `NaN`, `inf` and friends, as well
+/// as values that overflow to infinity, none of which are valid field values.
+///
+/// Short decimals, which is what metrics mostly look like, are converted with
+/// a couple of exact float operations; everything else goes through the
+/// standard library, so the result is always correctly rounded.
+pub fn parse_float(string_ref: &str) -> Option<f64> {
+    let bytes = string_ref.as_bytes();
+    let mut idx: usize = 0;
+
+    let negative = bytes.first() == Some(&b'-');
+    if negative {
+	idx += 1;
+    }
+
+    // Collect the digits into the mantissa while validating, it is only
+    // used when there are few enough of them not to wrap
+    let mut mantissa: u64 = 0;
+    let integer_start = idx;
+    while idx < bytes.len() && bytes[idx].is_ascii_digit() {
+	mantissa = mantissa.wrapping_mul(10).wrapping_add((bytes[idx] & 0x0f) as u64);
+	idx += 1;
+    }
+    let mut digits = idx - integer_start;
+    let mut fraction_digits: usize = 0;
+    if idx < bytes.len() && bytes[idx] == b'.' {
+	idx += 1;
+	let fraction_start = idx;
+	while idx < bytes.len() && bytes[idx].is_ascii_digit() {
+	    mantissa = mantissa.wrapping_mul(10).wrapping_add((bytes[idx] & 0x0f) as u64);
+	    idx += 1;
+	}
+	fraction_digits = idx - fraction_start;
+	digits += fraction_digits;
+    }
+    if digits == 0 {
+	return None;
+    }
+
+    let mut exponent: i64 = 0;
+    if idx < bytes.len() && (bytes[idx] == b'e' || bytes[idx] == b'E') {
+	idx += 1;
+	let exponent_negative = idx < bytes.len() && bytes[idx] == b'-';
+	if idx < bytes.len() && (bytes[idx] == b'+' || bytes[idx] == b'-') {
+	    idx += 1;
+	}
+	let exponent_start = idx;
+	while idx < bytes.len() && bytes[idx].is_ascii_digit() {
+	    // Anything this large is out of the fast path's range anyway
+	    exponent = exponent.saturating_mul(10).saturating_add((bytes[idx] & 0x0f) as i64);
+	    idx += 1;
+	}
+	if idx == exponent_start {
+	    return None;
+	}
+	if exponent_negative {
+	    exponent = -exponent;
+	}
+    }
+
+    if idx != bytes.len() {
+	return None;
+    }
+
+    let exponent = exponent.saturating_sub(fraction_digits as i64);
+    match number::parse_decimal_fast(mantissa, digits, exponent) {
+	Some(value) if negative => Some(-value),
+	Some(value) => Some(value),
+	None => string_ref.parse::<f64>().ok().filter(|value| value.is_finite()),
+    }
+}
+
+/// Remove the backslashes from `\,`, `\=` and `\ ` in a measurement, tag key,
+/// tag value or field key. A backslash before any other byte is kept as is.
+///
+/// Only allocates when an escape is actually present.
+pub fn unescape_identifier(string_ref: &str) -> Cow<'_, str> {
+    unescape(string_ref, b", =")
+}
+
+/// Remove the backslashes from `\"` and `\\` in a string field value (without
+/// its surrounding quotes). A backslash before any other byte is kept as is.
+///
+/// Only allocates when an escape is actually present.
+pub fn unescape_string(string_ref: &str) -> Cow<'_, str> {
+    unescape(string_ref, b"\"\\")
+}
+
+fn unescape<'a>(string_ref: &'a str, escapable: &[u8]) -> Cow<'a, str> {
+    let bytes = string_ref.as_bytes();
+    let Some(first) = bytes.iter().position(|&c| c == b'\\') else {
+	return Cow::Borrowed(string_ref);
+    };
+
+    let mut res: Vec<u8> = Vec::with_capacity(bytes.len());
+    res.extend_from_slice(&bytes[..first]);
+    let mut idx = first;
+    while idx < bytes.len() {
+	match bytes.get(idx..idx + 2) {
+	    Some([b'\\', next]) if escapable.contains(next) => {
+		res.push(*next);
+		idx += 2;
+	    },
+	    _ => {
+		res.push(bytes[idx]);
+		idx += 1;
+	    },
+	}
+    }
+
+    // Only ASCII backslashes in front of ASCII bytes were removed
+    Cow::Owned(unsafe {String::from_utf8_unchecked(res)})
+}
+
+/// Decode a field value, picking the type from its suffix: `i` for integers,
+/// `u` for unsigned integers, surrounding double quotes for strings and no
+/// suffix for floats. Anything else has to be one of the boolean spellings.
+fn parse_field_value(item: &str) -> Result<FieldValue<'_>, ParseErrorKind> {
+    match item.as_bytes() {
+	[b'"', .., b'"'] => Ok(FieldValue::String(unescape_string(unsafe {item.get_unchecked(1..item.len() - 1)}))),
+	[.., b'0'..=b'9', b'i'] => parse_int(item).map(FieldValue::Int).ok_or(ParseErrorKind::InvalidInteger),
+	[.., b'0'..=b'9', b'u'] => parse_uint(item).map(FieldValue::UInt).ok_or(ParseErrorKind::InvalidUnsigned),
+	[.., b'0'..=b'9' | b'.'] => parse_float(item).map(FieldValue::Float).ok_or(ParseErrorKind::InvalidFloat),
+	_ => parse_bool(item).map(FieldValue::Bool).ok_or(ParseErrorKind::InvalidFieldValue),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue<'input> {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(Cow<'input, str>),
+    Bool(bool),
+}
+
+impl FieldValue<'_> {
+    /// Copy out a borrowed string, so the value no longer borrows the input.
+    pub fn into_owned(self) -> FieldValue<'static> {
+	match self {
+	    FieldValue::Int(value) => FieldValue::Int(value),
+	    FieldValue::UInt(value) => FieldValue::UInt(value),
+	    FieldValue::Float(value) => FieldValue::Float(value),
+	    FieldValue::String(value) => FieldValue::String(Cow::Owned(value.into_owned())),
+	    FieldValue::Bool(value) => FieldValue::Bool(value),
+	}
+    }
+}
+
+/// Measurements, tag keys and values and field keys borrow from the input
+/// unless they contained an escape sequence.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node<'input> {
-    Measurement(&'input str),
-    Tag{key: &'input str, value: &'input str},
+    Measurement(Cow<'input, str>),
+    Tag{key: Cow<'input, str>, value: Cow<'input, str>},
     // Field{key: &'input str, value: &'input str},
-    Field{key: &'input str, value: u64},
+    Field{key: Cow<'input, str>, value: FieldValue<'input>},
     // Timestamp(&'input str),
-    Timestamp(u64),
+    Timestamp(i64),
+}
+
+impl Node<'_> {
+    /// Copy out everything borrowed, so the node no longer borrows the input.
+    pub fn into_owned(self) -> Node<'static> {
+	let owned = |text: Cow<'_, str>| Cow::Owned(text.into_owned());
+	match self {
+	    Node::Measurement(name) => Node::Measurement(owned(name)),
+	    Node::Tag{key, value} => Node::Tag{key: owned(key), value: owned(value)},
+	    Node::Field{key, value} => Node::Field{key: owned(key), value: value.into_owned()},
+	    Node::Timestamp(timestamp) => Node::Timestamp(timestamp),
+	}
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -107,7 +335,20 @@ pub fn gen_line() -> String {
 /// const uint8_t i = (1 << 3); // 0x08
 /// const uint8_t "\0" = (1 << 4); // 0x10
 /// const uint8_t "\n" = (1 << 5); // 0x20
-
+/// const uint8_t "\"" = (1 << 6); // 0x40
+/// const uint8_t "\\" = (1 << 7); // 0x80
+///
+/// Quotes are not structural themselves. They are reported in a separate
+/// bitmask which is turned into a mask of the bytes inside `"..."` (see
+/// `scanner::string_mask`), and structural characters under that mask are dropped.
+/// Only a quote right after an `=` in the field set opens a string, so spaces,
+/// `=` and line ends get bitmasks of their own to follow the phase with. A
+/// quote in a measurement, key or tag value is a plain byte.
+///
+/// Backslashes only use the top bit so their positions come straight out of
+/// the movemask. Bytes escaped by them (see `scanner::escaped_mask`) are neither
+/// structural nor quotes.
+///
 /// NOTES
 /// Have separate whitespace check to determine in which of the three phases we are:
 /// - Tags
@@ -117,480 +358,698 @@ pub fn gen_line() -> String {
 /// Might not need the seperate check when we use a queue model
 /// Process the queue from front till the end. When a whitespace is encountered
 /// switch states
+///
+/// # Panics
+///
+/// Panics when `record` is too long for `u32` offsets (4 GiB), see [`Parser`]
+/// for inputs of any length.
+///
+/// # Safety
+///
+/// The caller must ensure the CPU supports SSE4.1.
 #[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "sse4.1")]
-pub unsafe fn shuffle_lookup(record: &str) -> Vec<usize> {
-    use std::arch::x86_64::*;
-    const SIMD_LENGTH: usize = 16;
-    let mut res_vec : Vec<usize> = Vec::with_capacity(1_000_000);
-
-    //println!("{record}");
-    let len = record.len();
-    let lenminus16: usize = if len < SIMD_LENGTH { 0 } else { len - SIMD_LENGTH };
-    //println!("String len: {len}, minus 16: {lenminus16}");
-    let mut idx: usize = 0;
+pub unsafe fn shuffle_lookup(record: &str) -> Vec<u32> {
+    scanner::lookup::<scanner::Sse41>(record)
+}
 
-    let low_nibbles: [u8; 16] = [
-	// /* 0 */ 0x01 | 0x10 | 0x20, // " " | "\0" | "\n"
-	// /* 0 */ 0x01 | 0x20, // " " | "\n"
-	// /* 0 */ 0x01, // " "
-	/* 0 */ 0x01 | 0x10, // " " | "\0"
-	/* 1 */ 0x00,
-	/* 2 */ 0x00,
-	/* 3 */ 0x00,
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	// /* 9 */ 0x08, // "i"
-	/* 9 */ 0x00,
-	/* a */ 0x20, // "\n"
-	// /* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x02, // ","
-	/* d */ 0x04, // "="
-	/* e */ 0x00,
-	/* f */ 0x00,
-    ];
-    let high_nibbles: [u8; 16] = [
-	// /* 0 */ 0x10, // "\0"
-	/* 0 */ 0x10 | 0x20, // "\0" | "\n"
-	// /* 0 */ 0x00,
-	// /* 0 */ 0x20, // "\n"
-	/* 1 */ 0x00,
-	/* 2 */ 0x01 | 0x02, // " " | ","
-	/* 3 */ 0x04, // "="
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	// /* 6 */ 0x08, // "i"
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	/* 9 */ 0x00,
-	/* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x00, 
-	/* d */ 0x00,
-	/* e */ 0x00,
-	/* f */ 0x00,
-    ];
-
-    while idx < lenminus16 {
-	let mut chunk: [u8; SIMD_LENGTH] = [0x00; SIMD_LENGTH];
-	chunk.as_mut_ptr().copy_from(record.as_ptr().add(idx), SIMD_LENGTH);
-	let input = _mm_loadu_si128(chunk.as_ptr() as *const _);
-
-	let lower_nibbles = _mm_and_si128(input, _mm_set1_epi8(0x0F));
-	let higher_nibbles = _mm_and_si128(_mm_srli_epi16(input, 4), _mm_set1_epi8(0x0F));
-
-	let lo_translated = _mm_shuffle_epi8(
-	    _mm_load_si128(low_nibbles.as_ptr() as *const _), lower_nibbles);
-	let hi_translated = _mm_shuffle_epi8(
-	    _mm_load_si128(high_nibbles.as_ptr() as *const _), higher_nibbles);
-
-	let intersection = _mm_and_si128(lo_translated, hi_translated);
-
-	let t0 = _mm_cmpeq_epi8(intersection, _mm_setzero_si128());
-	let t1 = _mm_xor_si128(t0, _mm_cmpeq_epi8(t0, t0));
-
-	let mut bits = _mm_movemask_epi8(t1);
-
-	while bits != 0 {
-	    let v = bits.trailing_zeros() as i32;
-	    bits &= bits.wrapping_sub(1);
-	    let offset = v as usize + idx;
-	    //println!("{offset} ({v} -> '{}')", *record.as_bytes().get(offset).unwrap() as char);
-	    res_vec.push(offset);
-	}
+/// Stage 1 state carried from one chunk to the next.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ScanState {
+    prev_in_string: u64,
+    prev_escaped: u64,
+    // 1 when the line has reached its field set, and when the previous chunk
+    // ended in an `=` that may open a string
+    prev_in_fields: u64,
+    prev_equals: u64,
+    utf8: utf8::Utf8State,
+}
 
-	idx += SIMD_LENGTH;
-    }
+/// Classify one 16 byte chunk, returning a bitmask of its structural bytes.
+///
+/// # Safety
+///
+/// The caller must ensure the CPU supports SSE4.1.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn classify_sse41(chunk: &[u8; 16], state: &mut ScanState) -> u64 {
+    scanner::classify::<scanner::Sse41>(chunk, state)
+}
 
-    if idx < len {
-	let mut buf: [u8; SIMD_LENGTH] = [0x00; SIMD_LENGTH];
-	buf.as_mut_ptr().copy_from(record.as_ptr().add(idx), len - idx);
-	let input = _mm_loadu_si128(buf.as_ptr() as *const _);
+/// AVX2 variant of [`shuffle_lookup`], classifying 32 bytes per iteration.
+///
+/// # Panics
+///
+/// Panics when `record` is too long for `u32` offsets (4 GiB), see [`Parser`]
+/// for inputs of any length.
+///
+/// # Safety
+///
+/// The caller must ensure the CPU supports AVX2.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn shuffle_lookup_avx2(record: &str) -> Vec<u32> {
+    scanner::lookup::<scanner::Avx2>(record)
+}
 
-	let lower_nibbles = _mm_and_si128(input, _mm_set1_epi8(0x0F));
-	let higher_nibbles = _mm_and_si128(_mm_srli_epi16(input, 4), _mm_set1_epi8(0x0F));
+/// AVX-512 variant of [`shuffle_lookup`], classifying 64 bytes per iteration.
+///
+/// # Panics
+///
+/// Panics when `record` is too long for `u32` offsets (4 GiB), see [`Parser`]
+/// for inputs of any length.
+///
+/// # Safety
+///
+/// The caller must ensure the CPU supports AVX-512BW.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512bw")]
+pub unsafe fn shuffle_lookup_avx512(record: &str) -> Vec<u32> {
+    scanner::lookup::<scanner::Avx512>(record)
+}
 
-	let lo_translated = _mm_shuffle_epi8(
-	    _mm_load_si128(low_nibbles.as_ptr() as *const _), lower_nibbles);
-	let hi_translated = _mm_shuffle_epi8(
-	    _mm_load_si128(high_nibbles.as_ptr() as *const _), higher_nibbles);
+/// Portable variant of [`shuffle_lookup`], classifying 64 bytes per
+/// iteration eight at a time in plain `u64` arithmetic.
+///
+/// # Panics
+///
+/// Panics when `record` is too long for `u32` offsets (4 GiB).
+pub fn shuffle_lookup_swar(record: &str) -> Vec<u32> {
+    unsafe {scanner::lookup::<scanner::Swar>(record)}
+}
 
-	let intersection = _mm_and_si128(lo_translated, hi_translated);
+/// Parse `line` with the SSE4.1 scanner.
+///
+/// # Panics
+///
+/// Panics on malformed input, see [`try_parse_tape`] for the fallible version,
+/// and on CPUs without SSE4.1.
+#[cfg(target_arch = "x86_64")]
+pub fn parse_tape(line: &str) -> Vec<Node<'_>> {
+    try_parse_tape(line).unwrap_or_else(|err| panic!("{err}"))
+}
 
-	let t0 = _mm_cmpeq_epi8(intersection, _mm_setzero_si128());
-	let t1 = _mm_xor_si128(t0, _mm_cmpeq_epi8(t0, t0));
+/// Parse `line` with the AVX2 scanner.
+///
+/// # Panics
+///
+/// Panics on malformed input, see [`try_parse_tape_avx2`] for the fallible
+/// version, and on CPUs without AVX2.
+#[cfg(target_arch = "x86_64")]
+pub fn parse_tape_avx2(line: &str) -> Vec<Node<'_>> {
+    try_parse_tape_avx2(line).unwrap_or_else(|err| panic!("{err}"))
+}
 
-	let mut bits = _mm_movemask_epi8(t1);
+/// Parse `line` with the SSE4.1 scanner, stopping at the first malformed line.
+///
+/// # Panics
+///
+/// Panics on CPUs without SSE4.1, see [`parse`] for a version that picks
+/// whatever the CPU supports.
+#[cfg(target_arch = "x86_64")]
+pub fn try_parse_tape(line: &str) -> Result<Vec<Node<'_>>, ParseError> {
+    build_tape(line, Backend::Sse41.scanner(), &mut Vec::new(), &Parser::new(), &mut ScanState::default()).map(|parsed| parsed.nodes)
+}
 
-	while bits != 0 {
-	    let v = bits.trailing_zeros() as i32;
-	    bits &= bits.wrapping_sub(1);
-	    let offset = v as usize + idx;
-	    //println!("{offset} ({v})");
-	    //println!("{offset} ({v} -> '{}')", *record.as_bytes().get(offset).unwrap() as char);
-	    res_vec.push(offset);
-	    match record.as_bytes().get(offset) {
-		// Some(ch) => {
-		//     println!("{offset} ({v} -> '{}')", *ch as char);
-		// }
-		Some(_) => {}
-		None => {break}
-	    }
+/// Parse `line` with the AVX2 scanner, stopping at the first malformed line.
+///
+/// # Panics
+///
+/// Panics on CPUs without AVX2.
+#[cfg(target_arch = "x86_64")]
+pub fn try_parse_tape_avx2(line: &str) -> Result<Vec<Node<'_>>, ParseError> {
+    build_tape(line, Backend::Avx2.scanner(), &mut Vec::new(), &Parser::new(), &mut ScanState::default()).map(|parsed| parsed.nodes)
+}
+
+/// Parse `input` with the [active backend](Backend::active), stopping at the
+/// first malformed line.
+///
+/// ```
+/// use influx_parser::{parse, Node};
+///
+/// let nodes = parse("cpu usage=0.5 1000").unwrap();
+/// assert_eq!(nodes[0], Node::Measurement("cpu".into()));
+/// assert_eq!(nodes[2], Node::Timestamp(1000));
+/// ```
+pub fn parse(input: &str) -> Result<Vec<Node<'_>>, ParseError> {
+    build_tape(input, backend::active_scanner(), &mut Vec::new(), &Parser::new(), &mut ScanState::default())
+	.map(|parsed| parsed.nodes)
+}
+
+/// Like [`parse`], but for input that is not known to be UTF-8, e.g. read
+/// straight off the network. Stage 1 validates it in the same pass that
+/// finds the structural bytes, and invalid bytes are a [`ParseError`] of kind
+/// [`InvalidUtf8`](ParseErrorKind::InvalidUtf8) pointing at the first one.
+///
+/// ```
+/// use influx_parser::{parse_bytes, Node, ParseErrorKind};
+///
+/// let nodes = parse_bytes(b"cpu usage=0.5 1000").unwrap();
+/// assert_eq!(nodes[0], Node::Measurement("cpu".into()));
+///
+/// let err = parse_bytes(b"cpu usage=0.5 1000\nm\xFFem free=7i").unwrap_err();
+/// assert_eq!(err.kind, ParseErrorKind::InvalidUtf8);
+/// assert_eq!((err.offset, err.line, err.column), (20, 2, 2));
+/// ```
+pub fn parse_bytes(input: &[u8]) -> Result<Vec<Node<'_>>, ParseError> {
+    Parser::new().parse_bytes(input).map(|parsed| parsed.nodes)
+}
+
+/// Parse `input` and group the nodes by line.
+///
+/// ```
+/// use influx_parser::{parse_points, FieldValue};
+///
+/// let points = parse_points("cpu,host=a usage=0.5 1000\nmem free=7i\n").unwrap();
+/// assert_eq!(points.len(), 2);
+///
+/// let cpu = points.get(0).unwrap();
+/// assert_eq!(cpu.measurement, "cpu");
+/// assert_eq!(cpu.tag("host"), Some("a"));
+/// assert_eq!(cpu.field("usage"), Some(&FieldValue::Float(0.5)));
+/// assert_eq!(points.get(1).unwrap().timestamp, None);
+/// ```
+pub fn parse_points(input: &str) -> Result<Points<'_>, ParseError> {
+    parse(input).map(Points::from_tape)
+}
+
+/// Lazily parse `input` one point at a time, see [`PointIter`].
+pub fn iter_points(input: &str) -> PointIter<'_> {
+    PointIter::new(input)
+}
+
+/// Parser options for a batch of lines.
+///
+/// ```
+/// use influx_parser::Parser;
+///
+/// let parsed = Parser::new().lenient(true).parse("cpu usage=0.5\ncpu usage=x\n").unwrap();
+/// assert_eq!(parsed.nodes.len(), 2);
+/// assert_eq!(parsed.errors[0].span, 14..25);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    lenient: bool,
+    backend: Option<Backend>,
+    precision: Precision,
+    output_precision: Precision,
+    default_timestamp: Option<DefaultTimestamp>,
+    threads: usize,
+}
+
+/// Where the timestamp of a line without one comes from.
+#[derive(Clone)]
+enum DefaultTimestamp {
+    Fixed(i64),
+    Clock(Arc<dyn Fn() -> i64 + Send + Sync>),
+}
+
+impl DefaultTimestamp {
+    fn get(&self) -> i64 {
+	match self {
+	    DefaultTimestamp::Fixed(timestamp) => *timestamp,
+	    DefaultTimestamp::Clock(clock) => clock(),
 	}
     }
+}
 
-    res_vec
+impl fmt::Debug for DefaultTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    DefaultTimestamp::Fixed(timestamp) => f.debug_tuple("Fixed").field(timestamp).finish(),
+	    DefaultTimestamp::Clock(_) => f.write_str("Clock"),
+	}
+    }
 }
 
-#[cfg(target_arch = "x86_64")]
-#[target_feature(enable = "avx2")]
-pub unsafe fn shuffle_lookup_avx2(record: &str) -> Vec<usize> {
-    use std::arch::x86_64::*;
-    const SIMD_LENGTH: usize = 32;
-    let mut res_vec : Vec<usize> = Vec::with_capacity(1_000_000);
-
-    // //println!("{record}");
-    let len = record.len();
-    let lenminus16: usize = if len < SIMD_LENGTH { 0 } else { len - SIMD_LENGTH };
-    //println!("String len: {len}, minus 16: {lenminus16}");
-    let mut idx: usize = 0;
+impl Parser {
+    pub fn new() -> Self {
+	Self::default()
+    }
 
-    let low_nibbles: [u8; 32] = [
-	/* 0 */ 0x01 | 0x10, // " " | "\0"
-	/* 1 */ 0x00,
-	/* 2 */ 0x00,
-	/* 3 */ 0x00,
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	// /* 9 */ 0x08, // "i"
-	/* 9 */ 0x00,
-	/* a */ 0x20, // "\n"
-	// /* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x02, // ","
-	/* d */ 0x04, // "="
-	/* e */ 0x00,
-	/* f */ 0x00,
-	/* 0 */ 0x01 | 0x10, // " " | "\0"
-	/* 1 */ 0x00,
-	/* 2 */ 0x00,
-	/* 3 */ 0x00,
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	// /* 9 */ 0x08, // "i"
-	/* 9 */ 0x00,
-	/* a */ 0x20, // "\n"
-	// /* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x02, // ","
-	/* d */ 0x04, // "="
-	/* e */ 0x00,
-	/* f */ 0x00,
-    ];
-    let high_nibbles: [u8; 32] = [
-	/* 0 */ 0x20 | 0x10, // "\n" | "\0"
-	/* 1 */ 0x00,
-	/* 2 */ 0x01 | 0x02, // " " | ","
-	/* 3 */ 0x04, // "="
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	/* 9 */ 0x00,
-	/* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x00, 
-	/* d */ 0x00,
-	/* e */ 0x00,
-	/* f */ 0x00,
-	/* 0 */ 0x20 | 0x10, // "\n" | "\0"
-	/* 1 */ 0x00,
-	/* 2 */ 0x01 | 0x02, // " " | ","
-	/* 3 */ 0x04, // "="
-	/* 4 */ 0x00,
-	/* 5 */ 0x00,
-	/* 6 */ 0x00,
-	/* 7 */ 0x00,
-	/* 8 */ 0x00,
-	/* 9 */ 0x00,
-	/* a */ 0x00,
-	/* b */ 0x00,
-	/* c */ 0x00, 
-	/* d */ 0x00,
-	/* e */ 0x00,
-	/* f */ 0x00,
-    ];
-
-    let mut dst = [0 as u8; SIMD_LENGTH];
-    while idx < lenminus16 {
-	let mut chunk: [u8; SIMD_LENGTH] = [0x00; SIMD_LENGTH];
-	chunk.as_mut_ptr().copy_from(record.as_ptr().add(idx), SIMD_LENGTH);
-	let input = _mm256_loadu_si256(chunk.as_ptr() as *const _);
-
-	let lower_nibbles = _mm256_and_si256(input, _mm256_set1_epi8(0x0F));
-	let higher_nibbles = _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0x0F));
-
-	let lo_translated = _mm256_shuffle_epi8(
-	    _mm256_load_si256(low_nibbles.as_ptr() as *const _), lower_nibbles);
-	_mm256_storeu_si256(dst.as_mut_ptr() as *mut _, lo_translated);
-	let hi_translated = _mm256_shuffle_epi8(
-	    _mm256_load_si256(high_nibbles.as_ptr() as *const _), higher_nibbles);
-	_mm256_storeu_si256(dst.as_mut_ptr() as *mut _, hi_translated);
-
-	let intersection = _mm256_and_si256(lo_translated, hi_translated);
-
-	let t0 = _mm256_cmpeq_epi8(intersection, _mm256_setzero_si256());
-	let t1 = _mm256_xor_si256(t0, _mm256_cmpeq_epi8(t0, t0));
-
-	let mut bits = _mm256_movemask_epi8(t1);
-
-	while bits != 0 {
-	    let v = bits.trailing_zeros() as i32;
-	    bits &= bits.wrapping_sub(1);
-	    let offset = v as usize + idx;
-	    // println!("{offset} ({v} -> '{}')", *record.as_bytes().get(offset).unwrap() as char);
-	    res_vec.push(offset);
-	}
+    /// In lenient mode a malformed line is dropped and recorded in
+    /// [`Parsed::errors`] instead of failing the whole batch. Parsing picks up
+    /// again after the next newline.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+	self.lenient = lenient;
+	self
+    }
 
-	idx += SIMD_LENGTH;
+    /// Use `backend` for stage 1 instead of the [active one](Backend::active).
+    ///
+    /// [`parse`](Self::parse) panics when the CPU does not support it.
+    pub fn backend(mut self, backend: Backend) -> Self {
+	self.backend = Some(backend);
+	self
     }
 
-    if idx < len {
-	let mut buf: [u8; SIMD_LENGTH] = [0x00; SIMD_LENGTH];
-	buf.as_mut_ptr().copy_from(record.as_ptr().add(idx), len - idx);
-	let input = _mm256_loadu_si256(buf.as_ptr() as *const _);
+    /// The unit of the timestamps in the input, nanoseconds by default.
+    pub fn precision(mut self, precision: Precision) -> Self {
+	self.precision = precision;
+	self
+    }
 
-	let lower_nibbles = _mm256_and_si256(input, _mm256_set1_epi8(0x0F));
-	let higher_nibbles = _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0x0F));
+    /// The unit of [`Node::Timestamp`] in the output, nanoseconds by
+    /// default. Timestamps that do not fit an `i64` once converted to this
+    /// unit are reported as [`ParseErrorKind::TimestampOutOfRange`]; only the
+    /// converted value is checked, so a timestamp beyond the nanosecond range
+    /// is fine as long as the output is coarser.
+    ///
+    /// ```
+    /// use influx_parser::{Node, ParseErrorKind, Parser, Precision};
+    ///
+    /// let parsed = Parser::new().precision(Precision::Milliseconds).parse("cpu f=1i 1500").unwrap();
+    /// assert_eq!(parsed.nodes[2], Node::Timestamp(1_500_000_000));
+    ///
+    /// let parsed = Parser::new()
+    ///     .precision(Precision::Milliseconds)
+    ///     .output_precision(Precision::Seconds)
+    ///     .parse("cpu f=1i 1500")
+    ///     .unwrap();
+    /// assert_eq!(parsed.nodes[2], Node::Timestamp(1));
+    ///
+    /// let seconds = Parser::new().precision(Precision::Seconds);
+    /// let err = seconds.parse("cpu f=1i 10000000000").unwrap_err();
+    /// assert_eq!(err.kind, ParseErrorKind::TimestampOutOfRange);
+    /// let parsed = seconds.output_precision(Precision::Seconds).parse("cpu f=1i 10000000000").unwrap();
+    /// assert_eq!(parsed.nodes[2], Node::Timestamp(10_000_000_000));
+    /// ```
+    pub fn output_precision(mut self, precision: Precision) -> Self {
+	self.output_precision = precision;
+	self
+    }
 
-	let lo_translated = _mm256_shuffle_epi8(
-	    _mm256_load_si256(low_nibbles.as_ptr() as *const _), lower_nibbles);
-	// _mm256_storeu_si256(dst.as_mut_ptr() as *mut _, lo_translated);
-	let hi_translated = _mm256_shuffle_epi8(
-	    _mm256_load_si256(high_nibbles.as_ptr() as *const _), higher_nibbles);
+    /// Give lines without a timestamp this one, in the output precision,
+    /// instead of leaving [`Node::Timestamp`] out.
+    pub fn default_timestamp(mut self, timestamp: i64) -> Self {
+	self.default_timestamp = Some(DefaultTimestamp::Fixed(timestamp));
+	self
+    }
 
-	let intersection = _mm256_and_si256(lo_translated, hi_translated);
+    /// Like [`default_timestamp`](Self::default_timestamp), but ask `clock`
+    /// for the timestamp. It is called once per [`parse`](Self::parse), so
+    /// every line of a batch gets the same time, just like InfluxDB stamps a
+    /// whole write with the time it was received.
+    ///
+    /// ```
+    /// use influx_parser::{Node, Parser, Precision};
+    /// use std::time::{SystemTime, UNIX_EPOCH};
+    ///
+    /// let parser = Parser::new()
+    ///     .output_precision(Precision::Seconds)
+    ///     .default_timestamp_with(|| {
+    ///         SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    ///     });
+    /// let parsed = parser.parse("cpu f=1i\ncpu f=2i 1700000000000000000").unwrap();
+    /// assert!(matches!(parsed.nodes[2], Node::Timestamp(now) if now > 1700000000));
+    /// assert_eq!(parsed.nodes[5], Node::Timestamp(1700000000));
+    /// ```
+    pub fn default_timestamp_with<F>(mut self, clock: F) -> Self
+    where
+	F: Fn() -> i64 + Send + Sync + 'static,
+    {
+	self.default_timestamp = Some(DefaultTimestamp::Clock(Arc::new(clock)));
+	self
+    }
 
-	let t0 = _mm256_cmpeq_epi8(intersection, _mm256_setzero_si256());
-	let t1 = _mm256_xor_si256(t0, _mm256_cmpeq_epi8(t0, t0));
+    /// Parse large batches on up to `threads` threads, one by default.
+    ///
+    /// The input is split into runs of whole lines of about the same length,
+    /// each parsed on its own thread, and the nodes and errors are put back
+    /// together in input order. The result is the same as on one thread, error
+    /// positions included. Batches too small to be worth splitting are parsed
+    /// on the calling thread.
+    ///
+    /// ```
+    /// use influx_parser::{gen_line, Parser};
+    ///
+    /// let input: String = (0..10_000).map(|_| gen_line()).collect();
+    /// let parallel = Parser::new().threads(4).parse(&input).unwrap();
+    /// assert_eq!(parallel, Parser::new().parse(&input).unwrap());
+    /// ```
+    pub fn threads(mut self, threads: usize) -> Self {
+	self.threads = threads;
+	self
+    }
 
-	let mut bits = _mm256_movemask_epi8(t1);
+    pub fn parse<'a>(&self, input: &'a str) -> Result<Parsed<'a>, ParseError> {
+	self.parse_with(input, &mut Vec::new())
+    }
 
-	while bits != 0 {
-	    let v = bits.trailing_zeros() as i32;
-	    bits &= bits.wrapping_sub(1);
-	    let offset = v as usize + idx;
-	    res_vec.push(offset);
-	    match record.as_bytes().get(offset) {
-		Some(_) => {}
-		None => {break}
-	    }
+    /// Like [`parse`](Self::parse), but keep the structural index of stage 1
+    /// in `index` instead of allocating a new one. Whatever `index` holds is
+    /// overwritten, and it keeps its capacity between calls, so parsing many
+    /// small batches does not allocate an index for each of them.
+    ///
+    /// ```
+    /// use influx_parser::Parser;
+    ///
+    /// let parser = Parser::new();
+    /// let mut index = Vec::new();
+    /// for batch in ["cpu usage=0.5 1000\n", "mem free=7i 1000\n"] {
+    ///     let parsed = parser.parse_with(batch, &mut index).unwrap();
+    ///     assert_eq!(parsed.nodes.len(), 3);
+    /// }
+    /// assert!(index.capacity() > 0);
+    /// ```
+    pub fn parse_with<'a>(&self, input: &'a str, index: &mut Vec<u32>) -> Result<Parsed<'a>, ParseError> {
+	let scan = self.backend.map_or_else(backend::active_scanner, Backend::scanner);
+	if self.threads > 1 {
+	    return parallel::parse(input, scan, index, self);
+	}
+	build_tape(input, scan, index, self, &mut ScanState::default())
+    }
+
+    /// Like [`parse`](Self::parse), but for bytes, see [`parse_bytes`].
+    pub fn parse_bytes<'a>(&self, input: &'a [u8]) -> Result<Parsed<'a>, ParseError> {
+	self.parse_bytes_with(input, &mut Vec::new())
+    }
+
+    /// Like [`parse_with`](Self::parse_with), but for bytes, see
+    /// [`parse_bytes`].
+    pub fn parse_bytes_with<'a>(&self, input: &'a [u8], index: &mut Vec<u32>) -> Result<Parsed<'a>, ParseError> {
+	// Threads and segments start from a fresh stage 1 state, which would
+	// miss a sequence cut at their start. Inputs that big validate in a
+	// pass of their own instead.
+	if self.threads > 1 || input.len() > scanner::SEGMENT_SIZE {
+	    let input = std::str::from_utf8(input).map_err(|err| ParseError::invalid_utf8(input, err))?;
+	    return self.parse_with(input, index);
 	}
+	let scan = self.backend.map_or_else(backend::active_checked_scanner, Backend::checked_scanner);
+	build_tape_bytes(input, scan, index, self)
     }
-    
-    res_vec
 }
 
-pub fn parse_tape(line: &str) -> Vec<Node> {
-    let x = unsafe {shuffle_lookup(&line)};
-    let mut items: Vec<Node> = Vec::with_capacity(x.len());
+/// The nodes of a batch, together with the lines a lenient [`Parser`]
+/// skipped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parsed<'input> {
+    pub nodes: Vec<Node<'input>>,
+    pub errors: Vec<LineError>,
+}
+
+/// A malformed line skipped in lenient mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    /// Byte range of the whole line in the input, without its newline.
+    pub span: Range<usize>,
+    pub error: ParseError,
+}
+
+/// Stage 1 and 2: find the structural offsets with `scan`, one segment of
+/// the input at a time, and turn the bytes in between into nodes. `state` is
+/// left as stage 1 finished the input.
+fn build_tape<'a>(line: &'a str, scan: ScanFn, index: &mut Vec<u32>, parser: &Parser, state: &mut ScanState) -> Result<Parsed<'a>, ParseError> {
+    let mut stage2: Option<Stage2> = None;
 
-    let mut idx: usize = 0;
-    let mut phase: Phase = Phase::Measurement;
-
-    for offset in x {
-	if offset >= line.len() {
-	    if phase == Phase::Timestamp {
-		let item = unsafe {line.get_unchecked(idx..line.len())};
-		//println!("{item}");
-		items.push(Node::Timestamp(parse_int(item)));
+    // Offsets are u32, so inputs over 4 GiB are scanned in segments, each
+    // reusing the index. The stage 2 state carries over like the stage 1
+    // state does, so a line may well span two segments.
+    for (idx, segment) in line.as_bytes().chunks(scanner::SEGMENT_SIZE).enumerate() {
+	index.clear();
+	scan(segment, state, index);
+	// The tape is sized from the first segment, later ones grow it
+	let stage2 = stage2.get_or_insert_with(|| Stage2::new(line, parser, index.len()));
+	stage2.feed(index, idx * scanner::SEGMENT_SIZE)?;
+    }
+    stage2.unwrap_or_else(|| Stage2::new(line, parser, 0)).finish()
+}
+
+/// Stage 2 alone, for input stage 1 has already indexed elsewhere, e.g. in a
+/// stream's line buffer. `index` holds every structural offset of `line`, in
+/// order.
+pub(crate) fn build_tape_indexed<'a>(line: &'a str, index: &[u32], parser: &Parser) -> Result<Parsed<'a>, ParseError> {
+    let mut stage2 = Stage2::new(line, parser, index.len());
+    stage2.feed(index, 0)?;
+    stage2.finish()
+}
+
+/// Stage 1 with UTF-8 validation, then stage 2 once the input is known to be
+/// a `str`. Takes inputs of up to one segment.
+fn build_tape_bytes<'a>(input: &'a [u8], scan: ScanFn, index: &mut Vec<u32>, parser: &Parser) -> Result<Parsed<'a>, ParseError> {
+    debug_assert!(input.len() <= scanner::SEGMENT_SIZE);
+    let mut state = ScanState::default();
+    index.clear();
+    scan(input, &mut state, index);
+    if !state.utf8.is_valid() {
+	let err = std::str::from_utf8(input).expect_err("stage 1 found invalid UTF-8");
+	return Err(ParseError::invalid_utf8(input, err));
+    }
+
+    // SAFETY: stage 1 just validated it
+    let line = unsafe {std::str::from_utf8_unchecked(input)};
+    build_tape_indexed(line, index, parser)
+}
+
+/// Stage 2 of a whole batch: the tape, and in lenient mode the lines skipped
+/// so far.
+struct Stage2<'a, 'p> {
+    tape: TapeBuilder<'a>,
+    parser: &'p Parser,
+    errors: Vec<LineError>,
+    failed: Option<ParseError>,
+}
+
+impl<'a, 'p> Stage2<'a, 'p> {
+    /// `offsets` is how many structural offsets stage 1 found, or in its
+    /// first segment for longer inputs.
+    fn new(line: &'a str, parser: &'p Parser, offsets: usize) -> Self {
+	// Fields and tags take two structural bytes for their one node, which
+	// leaves room for a default timestamp, so a line never has more nodes
+	// than structural bytes, bar the virtual newline closing the last one
+	let mut tape = TapeBuilder::new(line, offsets + 1, line.as_bytes().contains(&b'\\'));
+	tape.precision = (parser.precision, parser.output_precision);
+	tape.default_timestamp = parser.default_timestamp.as_ref().map(DefaultTimestamp::get);
+	Stage2{tape, parser, errors: Vec::new(), failed: None}
+    }
+
+    /// Push the offsets of the segment starting at `base`.
+    fn feed(&mut self, index: &[u32], base: usize) -> Result<(), ParseError> {
+	// The scanners only report the end of the input when it falls inside
+	// their zero padded tail, so `finish` always ends on a virtual newline
+	// at the end to close the last line.
+	for &offset in index {
+	    let offset = base + offset as usize;
+	    if offset >= self.tape.line.len() {
+		break;
 	    }
-	    break;
+	    self.push(offset)?;
 	}
-	let item = unsafe {line.get_unchecked(idx..offset)};
-	// println!("{} {} ({:x}) {} {:?}", offset, line.as_bytes()[offset], line.as_bytes()[offset], item, phase);
-	match line.as_bytes()[offset] {
-	    0x20 => {//println!("SPACE");
-			match phase {
-			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::FieldSet;},
-			    Phase::TagSet => {
-				if let Node::Tag{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = item;
-				} else {unreachable!();}
-				phase = Phase::FieldSet;
-			    },
-			    Phase::FieldSet => {
-				if let Node::Field{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = parse_int(item);
-				} else {unreachable!();}
-				phase = Phase::Timestamp;
-			    },
-			    Phase::Timestamp => unreachable!()
-			}},
-	    0x2C => {//println!("Comma");
-			match phase {
-			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::TagSet;
-			    },
-			    Phase::TagSet => {
-				if let Node::Tag{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = item;
-				} else {unreachable!();}
-			    },
-			    Phase::FieldSet => {
-				// items.push(Node::Field{key : item, value : ""});
-				if let Node::Field{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = parse_int(item);
-				} else {unreachable!();}
-			    },
-			    _ => unreachable!()
-			}
-			},
-	    0x3D => {//println!("=");
-			match phase {
-			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::TagSet;
-			    },
-			    Phase::TagSet => {
-				items.push(Node::Tag{key : item, value : ""});
-			    },
-			    Phase::FieldSet => {
-				items.push(Node::Field{key : item, value : 0});
-			    },
-			    Phase::Timestamp => unreachable!()
-			}
-	    },
-	    0x00 | 0x0A => {// println!{"New line"};
-			match phase {
-			    Phase::Timestamp => {
-			    // let item = line.get_unchecked(item);
-			    //println!("{item}");
-				items.push(Node::Timestamp(parse_int(item)));
-				phase = Phase::Measurement;
-			    }
-			    _ => todo!("Reset phase and parse new influx line") 
-			}
-	    },
-	    _ => unreachable!()
+	Ok(())
+    }
+
+    fn finish(mut self) -> Result<Parsed<'a>, ParseError> {
+	self.push(self.tape.line.len())?;
+	Ok(Parsed{nodes: self.tape.items, errors: self.errors})
+    }
+
+    fn push(&mut self, offset: usize) -> Result<(), ParseError> {
+	if self.failed.is_none() {
+	    if let Err(err) = self.tape.push(offset) {
+		if !self.parser.lenient {
+		    return Err(err);
+		}
+		self.failed = Some(err);
+	    }
 	}
-    idx = offset + 1;
 
+	// Skip the rest of a malformed line, up to the next newline
+	if let Some(err) = self.failed.take_if(|_| self.tape.is_newline(offset)) {
+	    self.errors.push(LineError{span: self.tape.line_start..offset, error: err});
+	    self.tape.discard_line();
+	    self.tape.end_line(offset);
+	}
+	Ok(())
     }
-    items
 }
 
-pub fn parse_tape_avx2(line: &str) -> Vec<Node> {
-    let x = unsafe {shuffle_lookup_avx2(&line)};
-    let mut items: Vec<Node> = Vec::with_capacity(x.len());
+/// The stage 2 state machine, fed one structural offset at a time.
+pub(crate) struct TapeBuilder<'a> {
+    line: &'a str,
+    pub(crate) items: Vec<Node<'a>>,
+    // Clean input never has to look for escapes item by item
+    has_escapes: bool,
+    // Start of the item ending at the next offset
+    idx: usize,
+    line_start: usize,
+    // Number of nodes before the current line
+    line_start_node: usize,
+    // 1-based line number of `text_line_start`, the start of a line of text
+    // at or before `line_start`. Newlines in strings and NUL separators make
+    // the two differ, and they are only caught up on an error, so clean input
+    // never counts newlines
+    text_line: usize,
+    text_line_start: usize,
+    phase: Phase,
+    // Whether the last tag or field key still waits for its value
+    has_key: bool,
+    // Units of the timestamps in the input and on the tape
+    precision: (Precision, Precision),
+    // Timestamp for lines that have none
+    default_timestamp: Option<i64>,
+}
 
-    let mut idx: usize = 0;
-    let mut phase = Phase::Measurement;
-
-    for offset in x {
-	if offset >= line.len() {
-	    if phase == Phase::Timestamp {
-		let item = unsafe {line.get_unchecked(idx..line.len())};
-		//println!("{item}");
-		items.push(Node::Timestamp(parse_int(item)));
-	    }
-	    break;
+impl<'a> TapeBuilder<'a> {
+    /// `has_escapes` may only be false when `line` has no backslash at all.
+    pub(crate) fn new(line: &'a str, capacity: usize, has_escapes: bool) -> Self {
+	TapeBuilder {
+	    line,
+	    items: Vec::with_capacity(capacity),
+	    has_escapes,
+	    idx: 0,
+	    line_start: 0,
+	    line_start_node: 0,
+	    text_line: 1,
+	    text_line_start: 0,
+	    phase: Phase::Measurement,
+	    has_key: false,
+	    precision: (Precision::Nanoseconds, Precision::Nanoseconds),
+	    default_timestamp: None,
 	}
+    }
+
+    fn ident(&self, item: &'a str) -> Cow<'a, str> {
+	if self.has_escapes {unescape_identifier(item)} else {Cow::Borrowed(item)}
+    }
+
+    fn error(&mut self, kind: ParseErrorKind, start: usize, end: usize) -> ParseError {
+	(self.text_line, self.text_line_start) = line_of(self.line, self.text_line, self.text_line_start, self.line_start);
+	ParseError::new(kind, self.line, self.text_line, self.text_line_start, start, end)
+    }
+
+    /// The end of the input counts as a newline.
+    pub(crate) fn is_newline(&self, offset: usize) -> bool {
+	matches!(self.line.as_bytes().get(offset), None | Some(0x00 | 0x0A))
+    }
+
+    /// Drop the nodes of the current line.
+    fn discard_line(&mut self) {
+	self.items.truncate(self.line_start_node);
+    }
+
+    /// Hand out the nodes of the lines completed so far.
+    pub(crate) fn take_line(&mut self) -> Vec<Node<'a>> {
+	self.line_start_node = 0;
+	std::mem::take(&mut self.items)
+    }
+
+    /// Start a new line after the newline at `offset`.
+    fn end_line(&mut self, offset: usize) {
+	self.phase = Phase::Measurement;
+	self.has_key = false;
+	self.idx = offset + 1;
+	self.line_start = offset + 1;
+	self.line_start_node = self.items.len();
+    }
+
+    /// Consume the item ending at the separator at `offset`. An offset at the
+    /// end of the input closes the last line.
+    pub(crate) fn push(&mut self, offset: usize) -> Result<(), ParseError> {
+	let line = self.line;
+	let idx = self.idx;
 	let item = unsafe {line.get_unchecked(idx..offset)};
-	//println!("{} {} {} ", offset, line.as_bytes()[offset], item);
-	match line.as_bytes()[offset] {
-	    0x20 => {//println!("SPACE");
-			match phase {
-			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::FieldSet;},
-			    Phase::TagSet => {
-				if let Node::Tag{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = item;
-				} else {unreachable!();}
-				phase = Phase::FieldSet;
-			    },
-			    Phase::FieldSet => {
-				if let Node::Field{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = parse_int(item);
-				} else {unreachable!();}
-				phase = Phase::Timestamp;
-			    },
-			    Phase::Timestamp => unreachable!()
-			}},
-	    0x2C => {//println!("Comma");
-			match phase {
+	// println!("{} {} ({:x}) {} {:?}", offset, line.as_bytes()[offset], line.as_bytes()[offset], item, phase);
+	let separator = line.as_bytes().get(offset).copied().unwrap_or(0x0A);
+	match separator {
+	    0x20 | 0x2C => {//println!("SPACE / Comma");
+			match self.phase {
 			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::TagSet;
+				if item.is_empty() {
+				    return Err(self.error(ParseErrorKind::EmptyMeasurement, offset, offset + 1));
+				}
+				self.items.push(Node::Measurement(self.ident(item)));
+				self.phase = if separator == 0x20 {Phase::FieldSet} else {Phase::TagSet};
 			    },
 			    Phase::TagSet => {
-				if let Node::Tag{key: _, value} = items.last_mut().unwrap()
+				if !self.has_key {
+				    return Err(self.error(ParseErrorKind::MissingValue, idx, offset));
+				}
+				if item.is_empty() {
+				    return Err(self.error(ParseErrorKind::EmptyTagValue, offset, offset + 1));
+				}
+				let tag_value = self.ident(item);
+				if let Some(Node::Tag{key: _, value}) = self.items.last_mut()
 				{
-				    *value = item;
+				    *value = tag_value;
 				} else {unreachable!();}
+				self.has_key = false;
+				if separator == 0x20 {
+				    self.phase = Phase::FieldSet;
+				}
 			    },
 			    Phase::FieldSet => {
-				// items.push(Node::Field{key : item, value : ""});
-				if let Node::Field{key: _, value} = items.last_mut().unwrap()
-				{
-				    *value = parse_int(item);
-				} else {unreachable!();}
+				self.set_field_value(item).map_err(|kind| self.error(kind, idx, offset))?;
+				self.has_key = false;
+				if separator == 0x20 {
+				    self.phase = Phase::Timestamp;
+				}
 			    },
-			    _ => unreachable!()
-			}
-			},
+			    Phase::Timestamp => {
+				return Err(self.error(ParseErrorKind::UnexpectedSeparator(separator as char), offset, offset + 1));
+			    }
+			}},
 	    0x3D => {//println!("=");
-			match phase {
-			    Phase::Measurement => {
-				items.push(Node::Measurement(item));
-				phase = Phase::TagSet;
+			match self.phase {
+			    Phase::TagSet | Phase::FieldSet if !self.has_key => {
+				if item.is_empty() {
+				    return Err(self.error(ParseErrorKind::EmptyKey, offset, offset + 1));
+				}
+				if self.phase == Phase::TagSet {
+				    self.items.push(Node::Tag{key : self.ident(item), value : Cow::Borrowed("")});
+				} else {
+				    self.items.push(Node::Field{key : self.ident(item), value : FieldValue::Int(0)});
+				}
+				self.has_key = true;
 			    },
-			    Phase::TagSet => {
-				items.push(Node::Tag{key : item, value : ""});
+			    _ => return Err(self.error(ParseErrorKind::UnexpectedSeparator('='), offset, offset + 1)),
+			}
+	    },
+	    0x00 | 0x0A => {// println!{"New line"};
+			match self.phase {
+			    // Blank line
+			    Phase::Measurement if item.is_empty() => {},
+			    Phase::Measurement | Phase::TagSet => {
+				return Err(self.error(ParseErrorKind::MissingFieldSet, self.line_start, offset));
 			    },
 			    Phase::FieldSet => {
-				items.push(Node::Field{key : item, value : 0});
+				self.set_field_value(item).map_err(|kind| self.error(kind, idx, offset))?;
+				self.items.extend(self.default_timestamp.map(Node::Timestamp));
+			    },
+			    // A trailing space is not a timestamp
+			    Phase::Timestamp if item.is_empty() => {
+				self.items.extend(self.default_timestamp.map(Node::Timestamp));
 			    },
-			    Phase::Timestamp => unreachable!()
-			}
-	    },
-	    0x00 | 0x0A => {//println!{"New line"};
-			match phase {
 			    Phase::Timestamp => {
-			    // let item = line.get_unchecked(item);
-			    //println!("{item}");
-			    items.push(Node::Timestamp(parse_int(item)));
-			    phase = Phase::Measurement;
+				let timestamp = parse_timestamp(item).ok_or_else(|| self.error(ParseErrorKind::InvalidTimestamp, idx, offset))?;
+				let (from, to) = self.precision;
+				let timestamp = from.convert(timestamp, to).ok_or_else(|| self.error(ParseErrorKind::TimestampOutOfRange, idx, offset))?;
+				self.items.push(Node::Timestamp(timestamp));
 			    }
-			    _ => todo!("Reset phase and parse new influx line") 
 			}
+			self.end_line(offset);
+			return Ok(());
 	    },
 	    _ => unreachable!()
 	}
-    idx = offset + 1;
+	self.idx = offset + 1;
+	Ok(())
+    }
 
+    /// Fill in the value of the field whose key was just read, or report what
+    /// is missing when there is no such field.
+    fn set_field_value(&mut self, item: &'a str) -> Result<(), ParseErrorKind> {
+	if !self.has_key {
+	    // Either a dangling key or no field at all on this line
+	    return match self.items.last() {
+		Some(Node::Field{..}) => Err(ParseErrorKind::MissingValue),
+		_ if item.is_empty() => Err(ParseErrorKind::MissingFieldSet),
+		_ => Err(ParseErrorKind::MissingValue),
+	    };
+	}
+	if let Some(Node::Field{key: _, value}) = self.items.last_mut()
+	{
+	    *value = parse_field_value(item)?;
+	} else {unreachable!();}
+	Ok(())
     }
-    items
 }
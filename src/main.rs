@@ -1,4 +1,4 @@
-use influx_parser::{gen_line, parse_tape};
+use influx_parser::{gen_line, parse_tape, shuffle_lookup_avx2};
 use std::time::SystemTime;
 
 fn main() {
@@ -13,7 +13,7 @@ fn main() {
 
     let line0 = String::from(",=");
     let offsets = unsafe { shuffle_lookup_avx2(&line0) };
-    assert_eq!(offsets.len(), 2);
+    assert_eq!(offsets.len(), 3);
 
     let items = parse_tape(&line);
 
@@ -28,11 +28,44 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
+    use influx_parser::gen_line;
+    use influx_parser::iter_points;
+    use influx_parser::parse;
+    use influx_parser::parse_bool;
+    use influx_parser::parse_bytes;
+    use influx_parser::parse_file;
+    use influx_parser::parse_float;
     use influx_parser::parse_int;
+    use influx_parser::parse_points;
     use influx_parser::parse_tape;
+    use influx_parser::parse_tape_avx2;
+    use influx_parser::parse_timestamp;
+    use influx_parser::parse_uint;
+    use influx_parser::reference;
     use influx_parser::shuffle_lookup;
     use influx_parser::shuffle_lookup_avx2;
+    use influx_parser::shuffle_lookup_scalar;
+    use influx_parser::try_parse_tape;
+    use influx_parser::try_parse_tape_avx2;
+    use influx_parser::unescape_identifier;
+    use influx_parser::unescape_string;
+    use influx_parser::Backend;
+    use influx_parser::FieldValue;
+    use influx_parser::LineWriter;
     use influx_parser::Node;
+    use influx_parser::ParseError;
+    use influx_parser::ParseErrorKind;
+    use influx_parser::Parsed;
+    use influx_parser::Parser;
+    use influx_parser::PointBuf;
+    use influx_parser::Points;
+    use influx_parser::Precision;
+    use influx_parser::StreamError;
+    use influx_parser::StreamParser;
+    use rand::Rng;
+    use std::borrow::Cow;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
 
     #[test]
     fn basic() {
@@ -62,10 +95,1899 @@ mod tests {
         assert_eq!(res.len(), 12);
     }
 
+    #[test]
+    fn basic_all_backends() {
+        let table: [(&str, Vec<u32>); 3] = [
+            (",=", vec![0, 1, 2]),
+            (
+                "ab,cd=ef gh=15i,jk=16i 12345678",
+                vec![2, 5, 8, 11, 15, 18, 22, 31],
+            ),
+            ("ab gh=15i,jk=16i 12345678", vec![2, 5, 9, 12, 16, 25]),
+        ];
+        let line3 = "test,od27r=11YaN,bHueo=zzL78,JQB4N=txYCM,uIiRV=31biD,JdqDb=PFxji e65Xk=3772672500i,7Tdmm=964201946i,VygQy=888662919i,vC0Ic=2202051695i,t3GsG=4284953162i 1695559737257";
+        let line4 = "ab gh=15i,jk=16i 12345678\ncd,xe=la oiw=61i 12345678";
+
+        for backend in Backend::ALL {
+            if !backend.is_supported() {
+                continue;
+            }
+            let lookup = backend.lookup();
+            for (line, offsets) in &table {
+                assert_eq!(&lookup(line), offsets, "{} {line:?}", backend.name());
+            }
+            assert_eq!(lookup(line3).len(), 22, "{}", backend.name());
+            assert_eq!(lookup(line4).len(), 12, "{}", backend.name());
+        }
+    }
+
     #[test]
     fn parse_ints() {
-        assert_eq!(parse_int("64i"), 64);
-        assert_eq!(parse_int("1000000i"), 1000000);
+        assert_eq!(parse_int("64i"), Some(64));
+        assert_eq!(parse_int("1000000i"), Some(1000000));
+        assert_eq!(parse_int("-42i"), Some(-42));
+        assert_eq!(parse_int("0i"), Some(0));
+        assert_eq!(parse_int("-9223372036854775808i"), Some(i64::MIN));
+        assert_eq!(parse_uint("42u"), Some(42));
+        assert_eq!(parse_uint("18446744073709551615u"), Some(u64::MAX));
+
+        assert_eq!(parse_int("i"), None);
+        assert_eq!(parse_int("-i"), None);
+        assert_eq!(parse_int("1x2i"), None);
+        assert_eq!(parse_int("9223372036854775808i"), None);
+        assert_eq!(parse_int("-9223372036854775809i"), None);
+        assert_eq!(parse_uint(""), None);
+        assert_eq!(parse_uint("-1u"), None);
+        assert_eq!(parse_uint("18446744073709551616u"), None);
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse_timestamp("12345678"), Some(12345678));
+        assert_eq!(parse_timestamp("0"), Some(0));
+        assert_eq!(parse_timestamp("-86400000000000"), Some(-86400000000000));
+        assert_eq!(parse_timestamp("9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_timestamp("-9223372036854775808"), Some(i64::MIN));
+
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("-"), None);
+        assert_eq!(parse_timestamp("12i"), None);
+        assert_eq!(parse_timestamp("1.5"), None);
+        assert_eq!(parse_timestamp("+1"), None);
+        assert_eq!(parse_timestamp("9223372036854775808"), None);
+        assert_eq!(parse_timestamp("-9223372036854775809"), None);
+
+        let items = parse_tape("cpu f=1i -1000");
+        assert_eq!(items.last(), Some(&Node::Timestamp(-1000)));
+        let items = parse_tape_avx2("cpu f=1i -1000");
+        assert_eq!(items.last(), Some(&Node::Timestamp(-1000)));
+    }
+
+    #[test]
+    fn parse_digit_runs() {
+        // Every length goes through a different mix of the 16 digit, 8 digit
+        // and single digit paths
+        let digits = "98765432109876543210";
+        for len in 1..=20 {
+            let run = &digits[20 - len..];
+            let expected = run.parse::<u64>().ok();
+            assert_eq!(parse_uint(&format!("{run}u")), expected, "{run}");
+            assert_eq!(parse_timestamp(run), run.parse::<i64>().ok(), "{run}");
+            assert_eq!(
+                parse_timestamp(&format!("-{run}")),
+                format!("-{run}").parse::<i64>().ok(),
+                "{run}"
+            );
+
+            // Bytes right next to '0' and '9' must not slip through
+            for pos in 0..len {
+                for bad in [b'/', b':', b' ', b'a', 0x80] {
+                    let mut bytes = run.as_bytes().to_vec();
+                    bytes[pos] = bad;
+                    let bad = String::from_utf8_lossy(&bytes);
+                    assert_eq!(parse_timestamp(&bad), None, "{bad:?}");
+                }
+            }
+        }
+
+        // Zero padding does not count towards the length
+        assert_eq!(parse_uint("00000000000000000000001u"), Some(1));
+        assert_eq!(parse_uint("000000000000000000000000u"), Some(0));
+        assert_eq!(parse_uint("00000018446744073709551615u"), Some(u64::MAX));
+        assert_eq!(parse_uint("00000018446744073709551616u"), None);
+        assert_eq!(parse_int("-0000000000000000000000042i"), Some(-42));
+        assert_eq!(parse_timestamp("0000000000000000000001"), Some(1));
+        assert_eq!(parse_uint("0000000000000042u"), Some(42));
+        assert_eq!(
+            parse_timestamp("1700000000000000000"),
+            Some(1700000000000000000)
+        );
+        assert_eq!(parse_timestamp("99999999999999999999"), None);
+
+        let padded =
+            "cpu i=000000000000000000001i,u=0000000000000000000000042u 0000000000000000000001\n";
+        differential(padded);
+        assert_eq!(
+            parse(padded).unwrap()[1..],
+            [
+                Node::Field {
+                    key: "i".into(),
+                    value: FieldValue::Int(1)
+                },
+                Node::Field {
+                    key: "u".into(),
+                    value: FieldValue::UInt(42)
+                },
+                Node::Timestamp(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_bools() {
+        for value in ["t", "T", "true", "True", "TRUE"] {
+            assert_eq!(parse_bool(value), Some(true));
+        }
+        for value in ["f", "F", "false", "False", "FALSE"] {
+            assert_eq!(parse_bool(value), Some(false));
+        }
+        assert_eq!(parse_bool("tRUE"), None);
+        assert_eq!(parse_bool("yes"), None);
+        assert_eq!(parse_bool(""), None);
+    }
+
+    #[test]
+    fn parse_influx_field_types() {
+        let line = String::from("ab i=-42i,u=42u,f=-1.5,t=t,b=FALSE,s=\"x\" 12345678");
+        let expected = vec![
+            Node::Measurement("ab".into()),
+            Node::Field {
+                key: "i".into(),
+                value: FieldValue::Int(-42),
+            },
+            Node::Field {
+                key: "u".into(),
+                value: FieldValue::UInt(42),
+            },
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Float(-1.5),
+            },
+            Node::Field {
+                key: "t".into(),
+                value: FieldValue::Bool(true),
+            },
+            Node::Field {
+                key: "b".into(),
+                value: FieldValue::Bool(false),
+            },
+            Node::Field {
+                key: "s".into(),
+                value: FieldValue::String("x".into()),
+            },
+            Node::Timestamp(12345678),
+        ];
+        assert_eq!(parse_tape(&line), expected);
+        assert_eq!(parse_tape_avx2(&line), expected);
+    }
+
+    #[test]
+    fn parse_floats() {
+        assert_eq!(parse_float("0.64"), Some(0.64));
+        assert_eq!(parse_float("-1.5"), Some(-1.5));
+        assert_eq!(parse_float("1"), Some(1.0));
+        assert_eq!(parse_float("1."), Some(1.0));
+        assert_eq!(parse_float(".5"), Some(0.5));
+        assert_eq!(parse_float("1e3"), Some(1000.0));
+        assert_eq!(parse_float("-1.234456e+78"), Some(-1.234456e+78));
+        assert_eq!(parse_float("2.5E-3"), Some(0.0025));
+
+        assert_eq!(parse_float(""), None);
+        assert_eq!(parse_float("-"), None);
+        assert_eq!(parse_float("."), None);
+        assert_eq!(parse_float("1e"), None);
+        assert_eq!(parse_float("1.2.3"), None);
+        assert_eq!(parse_float("+1"), None);
+        assert_eq!(parse_float("NaN"), None);
+        assert_eq!(parse_float("inf"), None);
+        assert_eq!(parse_float("-infinity"), None);
+        assert_eq!(parse_float("1e400"), None);
+    }
+
+    #[test]
+    fn parse_floats_exact() {
+        // Both sides of the fast path must round exactly like the standard
+        // library
+        let same = |input: &str| {
+            let expected = input.parse::<f64>().unwrap();
+            let value = parse_float(input).unwrap();
+            assert_eq!(value.to_bits(), expected.to_bits(), "{input}");
+        };
+        for input in [
+            "0",
+            "-0",
+            "-0.0",
+            "0.1",
+            "0.3",
+            "123.456",
+            "9007199254740992",
+            "9007199254740993",
+            "0.000000000000000000000000001",
+            "1234567890123456789",
+            "12345678901234567890",
+            "1.7976931348623157e308",
+            "4.9e-324",
+            "2.2250738585072014e-308",
+            "1e22",
+            "1e23",
+            "1e-22",
+            "1e-23",
+            "3.141592653589793",
+            "1.00000000000000000000000000001",
+        ] {
+            same(input);
+        }
+        assert!(parse_float("-0").unwrap().is_sign_negative());
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..10000 {
+            let digits = rng.gen_range(0..12);
+            let integer: u64 = rng.gen_range(0..10u64.pow(digits));
+            let digits = rng.gen_range(1..12);
+            let fraction: u64 = rng.gen_range(0..10u64.pow(digits));
+            let exponent: i32 = rng.gen_range(-30..30);
+            same(&format!("{integer}.{fraction}"));
+            same(&format!("-{integer}.{fraction}e{exponent}"));
+            same(&format!("{integer}E{exponent}"));
+        }
+    }
+
+    #[test]
+    fn parse_influx_floats() {
+        let expected = vec![
+            Node::Measurement("cpu".into()),
+            Node::Tag {
+                key: "host".into(),
+                value: "a".into(),
+            },
+            Node::Field {
+                key: "usage".into(),
+                value: FieldValue::Float(0.64),
+            },
+            Node::Field {
+                key: "load".into(),
+                value: FieldValue::Float(1.5e3),
+            },
+            Node::Field {
+                key: "procs".into(),
+                value: FieldValue::Int(12),
+            },
+            Node::Timestamp(12345678),
+        ];
+
+        let line = String::from("cpu,host=a usage=0.64,load=1.5e3,procs=12i 12345678");
+        assert_eq!(parse_tape(&line), expected);
+        assert_eq!(parse_tape_avx2(&line), expected);
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let line = String::from("ab msg=\"disk full, retry=3\",x=1i 12345678");
+        assert_eq!(unsafe { shuffle_lookup(&line) }, vec![2, 6, 27, 29, 32, 41]);
+        assert_eq!(
+            unsafe { shuffle_lookup_avx2(&line) },
+            vec![2, 6, 27, 29, 32, 41]
+        );
+
+        // The string spans several SIMD chunks, so the in-string state has to be
+        // carried from one chunk to the next.
+        let line = String::from(
+            "ab msg=\"a b,c=d e f,g=h i j,k=l m n,o=p q r,s=t u v,w=x\",y=2i 12345678",
+        );
+        let expected = vec![
+            Node::Measurement("ab".into()),
+            Node::Field {
+                key: "msg".into(),
+                value: FieldValue::String("a b,c=d e f,g=h i j,k=l m n,o=p q r,s=t u v,w=x".into()),
+            },
+            Node::Field {
+                key: "y".into(),
+                value: FieldValue::Int(2),
+            },
+            Node::Timestamp(12345678),
+        ];
+        assert_eq!(parse_tape(&line), expected);
+        assert_eq!(parse_tape_avx2(&line), expected);
+
+        let line = String::from("ab empty=\"\",msg=\"disk full, retry=3\" 12345678");
+        let expected = vec![
+            Node::Measurement("ab".into()),
+            Node::Field {
+                key: "empty".into(),
+                value: FieldValue::String("".into()),
+            },
+            Node::Field {
+                key: "msg".into(),
+                value: FieldValue::String("disk full, retry=3".into()),
+            },
+            Node::Timestamp(12345678),
+        ];
+        assert_eq!(parse_tape(&line), expected);
+        assert_eq!(parse_tape_avx2(&line), expected);
+    }
+
+    #[test]
+    fn quotes_outside_field_values() {
+        // Only a quote right after an `=` in the field set opens a string,
+        // one anywhere else must not swallow the lines after it
+        let input = "cpu,a=x\"y f=1i 1\nmem f=2i 2\n";
+        let expected = vec![
+            Node::Measurement("cpu".into()),
+            Node::Tag {
+                key: "a".into(),
+                value: "x\"y".into(),
+            },
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Int(1),
+            },
+            Node::Timestamp(1),
+            Node::Measurement("mem".into()),
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Int(2),
+            },
+            Node::Timestamp(2),
+        ];
+        assert_eq!(parse(input), Ok(expected.clone()));
+        differential(input);
+        let parsed = Parser::new().lenient(true).parse(input).unwrap();
+        assert_eq!(parsed.nodes, expected);
+        assert!(parsed.errors.is_empty());
+
+        // In the measurement, tag keys, at the start of a tag value and in
+        // field keys, also where the block boundaries fall in between
+        for pad in 0..70 {
+            let measurement = format!("{}c\"pu", "m".repeat(pad));
+            let input = format!("{measurement},\"k=\"v,t=\"x f\"=1i,s=\"a b\n=c\" 1\nmem f=2i 2\n");
+            let nodes = parse(&input).unwrap();
+            assert_eq!(
+                nodes[..5],
+                [
+                    Node::Measurement(measurement.clone().into()),
+                    Node::Tag {
+                        key: "\"k".into(),
+                        value: "\"v".into(),
+                    },
+                    Node::Tag {
+                        key: "t".into(),
+                        value: "\"x".into(),
+                    },
+                    Node::Field {
+                        key: "f\"".into(),
+                        value: FieldValue::Int(1),
+                    },
+                    Node::Field {
+                        key: "s".into(),
+                        value: FieldValue::String("a b\n=c".into()),
+                    },
+                ],
+                "{input:?}"
+            );
+            assert_eq!(nodes.len(), 9);
+            differential(&input);
+        }
+
+        // A quote inside a field value does not open a string either
+        let input = "cpu f=x\"y g\" 1\nmem f=2i 2\n";
+        let err = parse(input).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidFieldValue);
+        assert_eq!(err.snippet, "x\"y");
+        differential(input);
+        let parsed = Parser::new().lenient(true).parse(input).unwrap();
+        assert_eq!(parsed.nodes.len(), 3);
+        assert_eq!(parsed.errors.len(), 1);
+    }
+
+    #[test]
+    fn unescape() {
+        assert!(matches!(unescape_identifier("cpu"), Cow::Borrowed("cpu")));
+        assert!(matches!(
+            unescape_string("disk full"),
+            Cow::Borrowed("disk full")
+        ));
+
+        assert_eq!(unescape_identifier(r"cpu\,core"), "cpu,core");
+        assert_eq!(unescape_identifier(r"host\ name\=x"), "host name=x");
+        assert_eq!(unescape_identifier(r"a\b\"), r"a\b\");
+        assert_eq!(unescape_string(r#"say \"hi\" \\ \n"#), r#"say "hi" \ \n"#);
+    }
+
+    #[test]
+    fn escapes() {
+        // Escaped separators are not structural, an escaped backslash does not
+        // escape the byte after it.
+        let line = String::from(r"cpu\,core=1\ 2 value=1i");
+        assert_eq!(unsafe { shuffle_lookup(&line) }, vec![9, 14, 20, 23]);
+        assert_eq!(unsafe { shuffle_lookup_avx2(&line) }, vec![9, 14, 20, 23]);
+        let line = String::from(r"a\\,b=c d=1i");
+        assert_eq!(unsafe { shuffle_lookup(&line) }, vec![3, 5, 7, 9, 12]);
+        assert_eq!(unsafe { shuffle_lookup_avx2(&line) }, vec![3, 5, 7, 9, 12]);
+
+        let line = String::from(
+            r#"cpu\,x,host\ name=a\=b\,c usage\ idle=1.5,msg="say \"hi\", ok" 12345678"#,
+        );
+        let expected = vec![
+            Node::Measurement("cpu,x".into()),
+            Node::Tag {
+                key: "host name".into(),
+                value: "a=b,c".into(),
+            },
+            Node::Field {
+                key: "usage idle".into(),
+                value: FieldValue::Float(1.5),
+            },
+            Node::Field {
+                key: "msg".into(),
+                value: FieldValue::String("say \"hi\", ok".into()),
+            },
+            Node::Timestamp(12345678),
+        ];
+        assert_eq!(parse_tape(&line), expected);
+        assert_eq!(parse_tape_avx2(&line), expected);
+
+        // A run of backslashes crossing a chunk boundary
+        let mut line = String::from("m");
+        line.push_str(&r"\".repeat(31));
+        line.push_str(", f=1i 12345678");
+        let items = parse_tape(&line);
+        assert_eq!(items, parse_tape_avx2(&line));
+        assert_eq!(
+            items[0],
+            Node::Measurement(format!("m{},", r"\".repeat(30)).into())
+        );
+
+        let items = parse_tape("cpu,host=a usage=1i 12345678");
+        assert!(matches!(&items[0], Node::Measurement(Cow::Borrowed("cpu"))));
+    }
+
+    #[test]
+    fn backends() {
+        assert_eq!(Backend::from_name("sse4.1"), Some(Backend::Sse41));
+        assert_eq!(Backend::from_name("neon"), None);
+        for backend in Backend::ALL {
+            assert_eq!(Backend::from_name(backend.name()), Some(backend));
+        }
+        assert!(Backend::Scalar.is_supported());
+        assert!(Backend::detect().is_supported());
+        assert!(Backend::active().is_supported());
+
+        // The scanners all agree with the scalar one, also on strings and
+        // escapes that cross a chunk boundary
+        let inputs = [
+            String::from("ab,cd=ef gh=15i,jk=16i 12345678"),
+            String::from(r#"ab msg="a b,c=d e f,g=h i j,k=l m n,o=p q r,s=t u v,w=x",y=2i 1"#),
+            String::from(r#"cpu\,x,host\ name=a\=b\,c usage\ idle=1.5,msg="say \"hi\", ok" 1"#),
+            format!("{}\\ x=1i", "a".repeat(15)),
+            format!("{}\\\\ x=1i", "a".repeat(14)),
+            format!(r#"a s="{}\" ,=",x=1i"#, "b".repeat(26)),
+            // Escape runs and strings carried from one 64 byte block to the next
+            format!("{}\\ x=1i", "a".repeat(63)),
+            format!("{}\\\\ x=1i", "a".repeat(62)),
+            format!("{}\\\\\\ x=1i", "a".repeat(61)),
+            format!(r#"a s="{}\" ,=",x=1i"#, "b".repeat(58)),
+            format!("{} x=1i", "a".repeat(64)),
+            "cpu f=1i\n".repeat(20),
+            String::new(),
+        ];
+        for input in &inputs {
+            let scalar = shuffle_lookup_scalar(input);
+            for backend in Backend::ALL {
+                if backend.is_supported() {
+                    let offsets = backend.lookup()(input);
+                    assert_eq!(offsets, scalar, "{} {input:?}", backend.name());
+                }
+            }
+        }
+        for input in &inputs {
+            differential(input);
+        }
+
+        assert_eq!(parse(&inputs[2]), try_parse_tape(&inputs[2]));
+    }
+
+    /// Compare every backend against the reference parser, nodes and errors.
+    fn differential(input: &str) {
+        let expected = reference::parse(input);
+        for backend in Backend::ALL {
+            if !backend.is_supported() {
+                continue;
+            }
+            let parsed = Parser::new()
+                .backend(backend)
+                .parse(input)
+                .map(|parsed| parsed.nodes);
+            assert_eq!(parsed, expected, "{} {input:?}", backend.name());
+            let parsed = Parser::new()
+                .backend(backend)
+                .parse_bytes(input.as_bytes())
+                .map(|parsed| parsed.nodes);
+            assert_eq!(parsed, expected, "{} bytes {input:?}", backend.name());
+        }
+    }
+
+    #[test]
+    fn differential_gen_line() {
+        let lines: Vec<String> = (0..2000).map(|_| gen_line()).collect();
+        for line in &lines {
+            differential(line);
+        }
+        for batch in lines.chunks(100) {
+            differential(&batch.concat());
+        }
+    }
+
+    #[test]
+    fn differential_adversarial() {
+        // Fragments that stress separators, escapes, quotes and values,
+        // especially where they straddle a chunk boundary
+        const FRAGMENTS: &[&str] = &[
+            "cpu",
+            "a",
+            "host",
+            "x",
+            " ",
+            ",",
+            "=",
+            "\n",
+            "\"",
+            "\\",
+            "\\\\",
+            "\\ ",
+            "\\,",
+            "\\=",
+            "\\\"",
+            "1",
+            "-1",
+            "12i",
+            "-5i",
+            "7u",
+            "1.5",
+            "-.5",
+            "1e3",
+            "2E-2",
+            "+1",
+            "t",
+            "FALSE",
+            "true",
+            "\"s p,a=c\"",
+            "12345678",
+            "-1700000000000000000",
+            "é",
+            "\0",
+            "9223372036854775808",
+            "18446744073709551616u",
+            "nan",
+            "inf",
+            "1.",
+            ".",
+            "-",
+        ];
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..5000 {
+            let len = rng.gen_range(0..40);
+            let input: String = (0..len)
+                .map(|_| FRAGMENTS[rng.gen_range(0..FRAGMENTS.len())])
+                .collect();
+            differential(&input);
+        }
+
+        // Mostly well formed lines with one fragment swapped in
+        for _ in 0..5000 {
+            let line = gen_line();
+            let mut cut = rng.gen_range(0..line.len());
+            while !line.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let fragment = FRAGMENTS[rng.gen_range(0..FRAGMENTS.len())];
+            let input = format!("{}{fragment}{}", &line[..cut], &line[cut + 1..]);
+            differential(&input);
+        }
+    }
+
+    #[test]
+    fn multi_line() {
+        let cpu = |timestamp: Option<i64>| {
+            let mut nodes = vec![
+                Node::Measurement("cpu".into()),
+                Node::Tag {
+                    key: "host".into(),
+                    value: "a".into(),
+                },
+                Node::Field {
+                    key: "usage".into(),
+                    value: FieldValue::Float(0.5),
+                },
+            ];
+            nodes.extend(timestamp.map(Node::Timestamp));
+            nodes
+        };
+        let mem = |timestamp: Option<i64>| {
+            let mut nodes = vec![
+                Node::Measurement("mem".into()),
+                Node::Field {
+                    key: "free".into(),
+                    value: FieldValue::Int(7),
+                },
+            ];
+            nodes.extend(timestamp.map(Node::Timestamp));
+            nodes
+        };
+
+        let cases = [
+            (
+                "cpu,host=a usage=0.5\nmem free=7i 12345678\n",
+                [cpu(None), mem(Some(12345678))].concat(),
+            ),
+            (
+                "cpu,host=a usage=0.5 12345678\nmem free=7i\n",
+                [cpu(Some(12345678)), mem(None)].concat(),
+            ),
+            (
+                "cpu,host=a usage=0.5\nmem free=7i",
+                [cpu(None), mem(None)].concat(),
+            ),
+            (
+                "cpu,host=a usage=0.5 12345678\n\nmem free=7i 12345678",
+                [cpu(Some(12345678)), mem(Some(12345678))].concat(),
+            ),
+            (
+                "\ncpu,host=a usage=0.5\ncpu,host=a usage=0.5 12345678\nmem free=7i\n\n",
+                [cpu(None), cpu(Some(12345678)), mem(None)].concat(),
+            ),
+            // Exactly one 64 byte block, so there is no zero padded tail that
+            // reports the end of the input
+            (
+                "mem free=7i 12345678\nmem free=7i 12345678\nmem free=7i 1234567890",
+                [
+                    mem(Some(12345678)),
+                    mem(Some(12345678)),
+                    mem(Some(1234567890)),
+                ]
+                .concat(),
+            ),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_tape(line), expected, "{line:?}");
+            assert_eq!(parse_tape_avx2(line), expected, "{line:?}");
+        }
+
+        let line4 = String::from("ab gh=15i,jk=16i 12345678\ncd,xe=la oiw=61i 12345678");
+        let items = parse_tape(&line4);
+        assert_eq!(items, parse_tape_avx2(&line4));
+        assert_eq!(items.len(), 8);
+        assert_eq!(items[4], Node::Measurement("cd".into()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (",a=b f=1i", ParseErrorKind::EmptyMeasurement, 0, 1, 1, ","),
+            ("cpu,=b f=1i", ParseErrorKind::EmptyKey, 4, 1, 5, "="),
+            ("cpu,a= f=1i", ParseErrorKind::EmptyTagValue, 6, 1, 7, " "),
+            ("cpu,a f=1i", ParseErrorKind::MissingValue, 4, 1, 5, "a"),
+            ("cpu f=1i,g 1", ParseErrorKind::MissingValue, 9, 1, 10, "g"),
+            ("cpu f=1i,g", ParseErrorKind::MissingValue, 9, 1, 10, "g"),
+            ("cpu f=1i,", ParseErrorKind::MissingValue, 9, 1, 10, ""),
+            (
+                "cpu f=1i\ncpu\n",
+                ParseErrorKind::MissingFieldSet,
+                9,
+                2,
+                1,
+                "cpu",
+            ),
+            (
+                "cpu,a=b\n",
+                ParseErrorKind::MissingFieldSet,
+                0,
+                1,
+                1,
+                "cpu,a=b",
+            ),
+            ("cpu,a=b ", ParseErrorKind::MissingFieldSet, 8, 1, 9, ""),
+            (
+                "cpu=1 f=1i",
+                ParseErrorKind::UnexpectedSeparator('='),
+                3,
+                1,
+                4,
+                "=",
+            ),
+            (
+                "cpu f=1i 1 2",
+                ParseErrorKind::UnexpectedSeparator(' '),
+                10,
+                1,
+                11,
+                " ",
+            ),
+            (
+                "cpu f=1i,g=2i=3i",
+                ParseErrorKind::UnexpectedSeparator('='),
+                13,
+                1,
+                14,
+                "=",
+            ),
+            (
+                "cpu f=1x2i",
+                ParseErrorKind::InvalidInteger,
+                6,
+                1,
+                7,
+                "1x2i",
+            ),
+            ("cpu f=-u", ParseErrorKind::InvalidFieldValue, 6, 1, 7, "-u"),
+            (
+                "cpu f=1.2.3",
+                ParseErrorKind::InvalidFloat,
+                6,
+                1,
+                7,
+                "1.2.3",
+            ),
+            ("cpu f=-1u", ParseErrorKind::InvalidUnsigned, 6, 1, 7, "-1u"),
+            (
+                "cpu f=yes",
+                ParseErrorKind::InvalidFieldValue,
+                6,
+                1,
+                7,
+                "yes",
+            ),
+            (
+                "cpu f=1i 12ab",
+                ParseErrorKind::InvalidTimestamp,
+                9,
+                1,
+                10,
+                "12ab",
+            ),
+            (
+                "cpu f=1i 12345678\nmém,hôst=ä f=NaN",
+                ParseErrorKind::InvalidFieldValue,
+                34,
+                2,
+                14,
+                "NaN",
+            ),
+        ];
+        for (line, kind, offset, line_no, column, snippet) in cases {
+            let expected = ParseError {
+                kind,
+                offset,
+                line: line_no,
+                column,
+                snippet: snippet.to_string(),
+            };
+            assert_eq!(try_parse_tape(line), Err(expected.clone()), "{line:?}");
+            assert_eq!(try_parse_tape_avx2(line), Err(expected), "{line:?}");
+        }
+
+        let err = try_parse_tape("cpu f=1i 12ab").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid timestamp at line 1, column 10: \"12ab\""
+        );
+
+        let long = format!("cpu f={}", "x".repeat(100));
+        assert_eq!(try_parse_tape(&long).unwrap_err().snippet, "x".repeat(64));
+    }
+
+    #[test]
+    fn lenient() {
+        let line = "cpu,host=a usage=0.5 12345678\n\
+                    cpu,host=b usage=oops 12345678\n\
+                    mem free=7i\n\
+                    cpu,host=c\n\
+                    mem free=8i 1 2\n\
+                    mem free=9i";
+        let parsed = Parser::new().lenient(true).parse(line).unwrap();
+        assert_eq!(
+            parsed.nodes,
+            vec![
+                Node::Measurement("cpu".into()),
+                Node::Tag {
+                    key: "host".into(),
+                    value: "a".into(),
+                },
+                Node::Field {
+                    key: "usage".into(),
+                    value: FieldValue::Float(0.5),
+                },
+                Node::Timestamp(12345678),
+                Node::Measurement("mem".into()),
+                Node::Field {
+                    key: "free".into(),
+                    value: FieldValue::Int(7),
+                },
+                Node::Measurement("mem".into()),
+                Node::Field {
+                    key: "free".into(),
+                    value: FieldValue::Int(9),
+                },
+            ]
+        );
+
+        let errors: Vec<_> = parsed
+            .errors
+            .iter()
+            .map(|err| (&line[err.span.clone()], err.error.kind, err.error.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "cpu,host=b usage=oops 12345678",
+                    ParseErrorKind::InvalidFieldValue,
+                    2
+                ),
+                ("cpu,host=c", ParseErrorKind::MissingFieldSet, 4),
+                (
+                    "mem free=8i 1 2",
+                    ParseErrorKind::UnexpectedSeparator(' '),
+                    5
+                ),
+            ]
+        );
+
+        // The last line fails without a trailing newline
+        let parsed = Parser::new()
+            .lenient(true)
+            .parse("mem free=7i\nmem free=")
+            .unwrap();
+        assert_eq!(parsed.nodes.len(), 2);
+        assert_eq!(parsed.errors[0].span, 12..21);
+
+        // Strict mode stops at the first error
+        let err = Parser::new().parse(line).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidFieldValue);
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn lenient_many_bad_lines() {
+        // Every error used to count the lines from the start of the input,
+        // which made this take minutes. Strings with a newline keep the line
+        // numbers of the tape and of the text apart.
+        let mut line = String::new();
+        let mut expected = Vec::new();
+        let mut text_line = 1;
+        for idx in 0..100_000 {
+            if idx % 10 == 0 {
+                line.push_str("mem s=\"a\nb\" 1\n");
+                text_line += 2;
+            } else {
+                line.push_str("cpu f=oops\n");
+                expected.push((ParseErrorKind::InvalidFieldValue, text_line, 7));
+                text_line += 1;
+            }
+        }
+
+        let parsed = Parser::new().lenient(true).parse(&line).unwrap();
+        assert_eq!(parsed.nodes.len(), 10_000 * 3);
+        let errors: Vec<_> = parsed
+            .errors
+            .iter()
+            .map(|err| (err.error.kind, err.error.line, err.error.column))
+            .collect();
+        assert_eq!(errors, expected);
+
+        let err = Parser::new().parse(&line).unwrap_err();
+        assert_eq!((err.line, err.column), (3, 7));
+    }
+
+    #[test]
+    fn precision() {
+        let timestamps = |parser: Parser, input: &str| -> Vec<i64> {
+            let parsed = parser.parse(input).unwrap();
+            parsed
+                .nodes
+                .iter()
+                .filter_map(|node| match node {
+                    Node::Timestamp(timestamp) => Some(*timestamp),
+                    _ => None,
+                })
+                .collect()
+        };
+        let line = "cpu f=1i 1700000000
+cpu f=1i -1500
+cpu f=1i 0";
+
+        assert_eq!(timestamps(Parser::new(), line), vec![1700000000, -1500, 0]);
+        assert_eq!(
+            timestamps(Parser::new().precision(Precision::Seconds), line),
+            vec![1700000000000000000, -1500000000000, 0]
+        );
+        assert_eq!(
+            timestamps(Parser::new().precision(Precision::Microseconds), line),
+            vec![1700000000000, -1500000, 0]
+        );
+        assert_eq!(
+            timestamps(
+                Parser::new()
+                    .precision(Precision::Milliseconds)
+                    .output_precision(Precision::Seconds),
+                line
+            ),
+            vec![1700000, -2, 0]
+        );
+        assert_eq!(
+            timestamps(
+                Parser::new().output_precision(Precision::Microseconds),
+                line
+            ),
+            vec![1700000, -2, 0]
+        );
+
+        // Sources with different precisions end up comparable
+        let ms = Parser::new().precision(Precision::Milliseconds);
+        let s = Parser::new().precision(Precision::Seconds);
+        assert_eq!(
+            timestamps(ms, "cpu f=1i 1700000000000"),
+            timestamps(s, "cpu f=1i 1700000000")
+        );
+
+        let err = Parser::new()
+            .precision(Precision::Seconds)
+            .parse(
+                "cpu f=1i 0
+cpu f=1i 9300000000",
+            )
+            .unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TimestampOutOfRange);
+        assert_eq!((err.line, err.column), (2, 10));
+        assert_eq!(err.snippet, "9300000000");
+
+        let parsed = Parser::new()
+            .lenient(true)
+            .precision(Precision::Seconds)
+            .parse(
+                "cpu f=1i -9300000000
+cpu f=1i 1",
+            )
+            .unwrap();
+        assert_eq!(parsed.nodes.len(), 3);
+        assert_eq!(parsed.errors[0].span, 0..20);
+    }
+
+    #[test]
+    fn default_timestamp() {
+        let line = "cpu f=1i\ncpu f=2i 5\n\ncpu f=3i \ncpu f=4i";
+        let timestamps = |parsed: &Parsed| -> Vec<Option<i64>> {
+            Points::try_from(parsed.nodes.clone())
+                .unwrap()
+                .iter()
+                .map(|point| point.timestamp)
+                .collect()
+        };
+
+        let parsed = Parser::new().parse(line).unwrap();
+        assert_eq!(timestamps(&parsed), vec![None, Some(5), None, None]);
+
+        let parsed = Parser::new().default_timestamp(42).parse(line).unwrap();
+        assert_eq!(
+            timestamps(&parsed),
+            vec![Some(42), Some(5), Some(42), Some(42)]
+        );
+
+        // The default is already in the output precision
+        let parsed = Parser::new()
+            .precision(Precision::Seconds)
+            .default_timestamp(42)
+            .parse(line)
+            .unwrap();
+        assert_eq!(
+            timestamps(&parsed),
+            vec![Some(42), Some(5000000000), Some(42), Some(42)]
+        );
+
+        // The clock is read once per batch
+        let calls = Arc::new(AtomicI64::new(0));
+        let clock = {
+            let calls = calls.clone();
+            move || calls.fetch_add(1, Ordering::Relaxed) + 100
+        };
+        let parser = Parser::new().default_timestamp_with(clock);
+        let parsed = parser.parse(line).unwrap();
+        assert_eq!(
+            timestamps(&parsed),
+            vec![Some(100), Some(5), Some(100), Some(100)]
+        );
+        let parsed = parser.clone().parse(line).unwrap();
+        assert_eq!(
+            timestamps(&parsed),
+            vec![Some(101), Some(5), Some(101), Some(101)]
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // Skipped lines do not leave a timestamp behind
+        let parsed = Parser::new()
+            .lenient(true)
+            .default_timestamp(42)
+            .parse("cpu f=x\ncpu f=1i")
+            .unwrap();
+        assert_eq!(parsed.nodes.len(), 3);
+        assert_eq!(timestamps(&parsed), vec![Some(42)]);
+    }
+
+    #[test]
+    fn reused_index() {
+        let big: String = (0..200).map(|_| gen_line()).collect();
+        let batches = [big.as_str(), "cpu f=1i 5", "", "cpu f=x", "mem free=7i\n"];
+
+        for backend in Backend::ALL {
+            if !backend.is_supported() {
+                continue;
+            }
+            let parser = Parser::new().backend(backend);
+            let mut index = Vec::new();
+            for batch in batches {
+                // Offsets left over from a longer batch must not show up
+                assert_eq!(
+                    parser.parse_with(batch, &mut index),
+                    parser.parse(batch),
+                    "{} {batch:?}",
+                    backend.name()
+                );
+            }
+            assert!(index.capacity() >= big.len() / 4);
+        }
+    }
+
+    #[test]
+    fn utf8() {
+        let check = |input: &[u8]| {
+            let expected = std::str::from_utf8(input).map_err(|err| err.valid_up_to());
+            for backend in Backend::ALL {
+                if !backend.is_supported() {
+                    continue;
+                }
+                let parsed = Parser::new()
+                    .lenient(true)
+                    .backend(backend)
+                    .parse_bytes(input);
+                match (&parsed, expected) {
+                    (Ok(_), Ok(_)) => {}
+                    (Err(err), Err(offset)) => {
+                        assert_eq!(err.kind, ParseErrorKind::InvalidUtf8);
+                        assert_eq!(err.offset, offset, "{} {input:?}", backend.name());
+                    }
+                    _ => panic!("{} {input:?}: {parsed:?}", backend.name()),
+                }
+            }
+        };
+
+        const SEQUENCES: &[&[u8]] = &[
+            // Valid
+            b"a",
+            "\u{7F}".as_bytes(),
+            "\u{80}".as_bytes(),
+            "\u{7FF}".as_bytes(),
+            "\u{800}".as_bytes(),
+            "\u{D7FF}".as_bytes(),
+            "\u{E000}".as_bytes(),
+            "\u{FFFF}".as_bytes(),
+            "\u{10000}".as_bytes(),
+            "\u{10FFFF}".as_bytes(),
+            // Invalid
+            b"\x80",
+            b"\xBF",
+            b"\xC0\x80",
+            b"\xC1\xBF",
+            b"\xC2",
+            b"\xC2\xC2",
+            b"\xE0\x80\x80",
+            b"\xE0\x9F\xBF",
+            b"\xE1\x80",
+            b"\xED\xA0\x80",
+            b"\xED\xBF\xBF",
+            b"\xF0\x80\x80\x80",
+            b"\xF0\x8F\xBF\xBF",
+            b"\xF1\x80\x80",
+            b"\xF4\x90\x80\x80",
+            b"\xF5\x80\x80\x80",
+            b"\xFF",
+            b"\xE2\x82\xAC\x80",
+        ];
+        // Every sequence at every position around the block boundaries, in a
+        // string field and at the very end of the input
+        for sequence in SEQUENCES {
+            for at in (0..80).chain(120..136) {
+                let mut input = b"cpu s=\"".to_vec();
+                input.resize(at.max(input.len()), b'x');
+                input.extend_from_slice(sequence);
+                check(&input);
+                input.extend_from_slice(b"\" 1\n");
+                check(&input);
+            }
+        }
+
+        // Random bytes, mostly valid
+        let mut rng = rand::thread_rng();
+        let chars = ['a', ' ', '\n', '\u{E9}', '\u{20AC}', '\u{1F600}'];
+        for _ in 0..2000 {
+            let mut input = String::new();
+            for _ in 0..rng.gen_range(0..200) {
+                input.push(chars[rng.gen_range(0..chars.len())]);
+            }
+            let mut input = input.into_bytes();
+            if !input.is_empty() && rng.gen_bool(0.5) {
+                let idx = rng.gen_range(0..input.len());
+                input[idx] = rng.gen();
+            }
+            check(&input);
+        }
+
+        let err = parse_bytes(b"cpu f=1i\nmem s=\"\xE2\x82\" 1\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 8));
+        assert_eq!(err.snippet, "\u{FFFD}");
+
+        // Too many threads for one pass are checked all the same
+        let input = format!("{}m\u{E9}m f=1i\n", "cpu f=1i\n".repeat(100_000));
+        let parser = Parser::new().threads(4);
+        assert_eq!(parser.parse_bytes(input.as_bytes()), parser.parse(&input));
+        let mut bytes = input.into_bytes();
+        bytes[900_001] = 0xE9;
+        let err = parser.parse_bytes(&bytes).unwrap_err();
+        assert_eq!((err.offset, err.line), (900_001, 100_001));
+    }
+
+    #[test]
+    fn parallel() {
+        let lines: Vec<String> = (0..10_000).map(|_| gen_line()).collect();
+        let same = |input: &str, parser: Parser| {
+            let expected = parser.clone().parse(input);
+            for threads in [2, 3, 4, 7] {
+                let parallel = parser.clone().threads(threads).parse(input);
+                assert_eq!(parallel, expected, "{threads} threads");
+            }
+        };
+
+        let input = lines.concat();
+        same(&input, Parser::new());
+        same("cpu f=1i\n", Parser::new());
+        same("", Parser::new());
+
+        // Long strings full of newlines, so chunks get cut inside them
+        let string = format!("log msg=\"{}\" 5\n", "x\n".repeat(50_000));
+        let input = format!(
+            "{}{string}{}",
+            lines[..5000].concat(),
+            lines[5000..].concat()
+        );
+        same(&input, Parser::new());
+        let input = string.repeat(20);
+        same(&input, Parser::new());
+
+        // Newlines that may be escaped are not cut at
+        let input = "cpu\\\nx f=1i\n".repeat(100_000);
+        same(&input, Parser::new());
+
+        // Errors far into the input keep their position
+        let mut broken = lines.clone();
+        broken[7000] = String::from("cpu f=x\n");
+        broken[9000] = String::from("mem f\n");
+        let input = broken.concat();
+        let err = Parser::new().threads(4).parse(&input).unwrap_err();
+        assert_eq!(err.line, 7001);
+        same(&input, Parser::new());
+        let parsed = Parser::new()
+            .lenient(true)
+            .threads(4)
+            .parse(&input)
+            .unwrap();
+        assert_eq!(parsed.errors.len(), 2);
+        assert_eq!(parsed.errors[1].error.line, 9001);
+        same(&input, Parser::new().lenient(true));
+
+        // The clock is read once for all threads
+        let input: String = (0..100_000).map(|idx| format!("cpu f={idx}i\n")).collect();
+        let calls = Arc::new(AtomicI64::new(0));
+        let clock = {
+            let calls = calls.clone();
+            move || calls.fetch_add(1, Ordering::Relaxed)
+        };
+        let parser = Parser::new().threads(4).default_timestamp_with(clock);
+        let parsed = parser.parse(&input).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(parsed
+            .nodes
+            .iter()
+            .all(|node| !matches!(node, Node::Timestamp(ts) if *ts != 0)));
+    }
+
+    #[test]
+    fn points() {
+        let line = "cpu,host=a,region=eu usage=0.5,idle=2i 12345678\n\
+                    mem free=7i\n\
+                    \n\
+                    disk,path=/ used=1u 12345678";
+        let points = parse_points(line).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points.nodes().len(), 12);
+
+        let cpu = points.get(0).unwrap();
+        assert_eq!(cpu.measurement, "cpu");
+        assert_eq!(
+            cpu.tags,
+            &[
+                Node::Tag {
+                    key: "host".into(),
+                    value: "a".into(),
+                },
+                Node::Tag {
+                    key: "region".into(),
+                    value: "eu".into(),
+                },
+            ]
+        );
+        assert_eq!(cpu.fields.len(), 2);
+        assert_eq!(cpu.tag("region"), Some("eu"));
+        assert_eq!(cpu.tag("missing"), None);
+        assert_eq!(cpu.field("idle"), Some(&FieldValue::Int(2)));
+        assert_eq!(cpu.timestamp, Some(12345678));
+
+        let mem = points.get(1).unwrap();
+        assert_eq!(mem.measurement, "mem");
+        assert!(mem.tags.is_empty());
+        assert_eq!(
+            mem.fields,
+            &[Node::Field {
+                key: "free".into(),
+                value: FieldValue::Int(7),
+            }]
+        );
+        assert_eq!(mem.timestamp, None);
+
+        let measurements: Vec<_> = points.iter().map(|point| point.measurement).collect();
+        assert_eq!(measurements, vec!["cpu", "mem", "disk"]);
+        assert!(points.get(3).is_none());
+
+        // Points can be built from the output of a lenient parser as well
+        let parsed = Parser::new()
+            .lenient(true)
+            .parse("mem free=7i\nbad\nmem free=8i")
+            .unwrap();
+        let points = Points::try_from(parsed.nodes).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(
+            points.get(1).unwrap().field("free"),
+            Some(&FieldValue::Int(8))
+        );
+
+        assert!(parse_points("").unwrap().is_empty());
+
+        // Anything else is handed back rather than grouped
+        let measurement = || Node::Measurement("cpu".into());
+        let field = || Node::Field {
+            key: "f".into(),
+            value: FieldValue::Int(1),
+        };
+        let tag = || Node::Tag {
+            key: "t".into(),
+            value: "a".into(),
+        };
+        for nodes in [
+            vec![field()],
+            vec![measurement()],
+            vec![measurement(), tag()],
+            vec![measurement(), measurement(), field()],
+            vec![measurement(), field(), tag()],
+            vec![measurement(), Node::Timestamp(1), field()],
+            vec![measurement(), field(), Node::Timestamp(1), field()],
+            vec![
+                measurement(),
+                field(),
+                Node::Timestamp(1),
+                Node::Timestamp(2),
+            ],
+        ] {
+            assert_eq!(Points::try_from(nodes.clone()), Err(nodes.clone()));
+            assert_eq!(PointBuf::try_from(nodes.clone()), Err(nodes));
+        }
+        let nodes = vec![measurement(), tag(), field(), field(), Node::Timestamp(1)];
+        let point = PointBuf::try_from(nodes.clone()).unwrap();
+        assert_eq!(point.point().fields.len(), 2);
+        let two_points = [nodes.clone(), nodes].concat();
+        assert_eq!(Points::try_from(two_points.clone()).unwrap().len(), 2);
+        assert_eq!(PointBuf::try_from(two_points.clone()), Err(two_points));
+    }
+
+    #[test]
+    fn point_iter() {
+        let mut lines: Vec<String> = (0..50).map(|_| gen_line()).collect();
+        lines.push(String::from(
+            "log,host\\ name=a msg=\"a long message, with separators = and\\n newlines\",x=1.5\n",
+        ));
+        lines.push(String::from("\nmem free=7i"));
+        let input = lines.concat();
+
+        let points = parse_points(&input).unwrap();
+        let lazy: Vec<_> = iter_points(&input).map(Result::unwrap).collect();
+        assert_eq!(lazy.len(), points.len());
+        for (buf, point) in lazy.iter().zip(points.iter()) {
+            assert_eq!(buf.point(), point);
+        }
+
+        // Stops at the first error, after the points before it
+        let mut iter = iter_points("mem free=7i\nmem free=\nmem free=8i\n");
+        assert_eq!(
+            iter.next().unwrap().unwrap().point().field("free"),
+            Some(&FieldValue::Int(7))
+        );
+        assert_eq!(iter.next().unwrap().unwrap_err().line, 2);
+        assert!(iter.next().is_none());
+
+        assert!(iter_points("").next().is_none());
+        assert!(iter_points("\n\n").next().is_none());
+        assert_eq!(iter_points("m f=1").take(5).count(), 1);
+    }
+
+    #[test]
+    fn mapped_file() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(format!("influx_parser_{}_{name}", std::process::id()));
+        // SAFETY: nothing writes to the files while they are mapped
+        let parse_file = |path: &std::path::Path| unsafe { parse_file(path) };
+
+        let input: String = (0..100).map(|_| gen_line()).collect();
+        let lines = path("lines.lp");
+        std::fs::write(&lines, &input).unwrap();
+        let file = parse_file(&lines).unwrap();
+        assert_eq!(file.as_str(), input);
+        let expected = parse_points(&input).unwrap();
+        assert_eq!(file.parse().unwrap(), expected);
+        let lazy: Vec<_> = file.points().map(Result::unwrap).collect();
+        assert_eq!(lazy.len(), expected.len());
+        for (point, expected) in lazy.iter().zip(expected.iter()) {
+            assert_eq!(point.point(), expected);
+        }
+
+        let empty = path("empty.lp");
+        std::fs::write(&empty, "").unwrap();
+        assert!(parse_file(&empty).unwrap().points().next().is_none());
+
+        let invalid = path("invalid.lp");
+        std::fs::write(&invalid, b"cpu f=1i\ncpu s=\"\xff\"\n").unwrap();
+        assert!(matches!(
+            parse_file(&invalid),
+            Err(StreamError::InvalidUtf8 { offset: 16 })
+        ));
+
+        assert!(matches!(
+            parse_file(&path("missing.lp")),
+            Err(StreamError::Io(_))
+        ));
+
+        for path in [lines, empty, invalid] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    /// Hands out at most `step` bytes per read.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.step.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn stream_parser() {
+        let mut lines: Vec<String> = (0..20).map(|_| gen_line()).collect();
+        lines.push(String::from(
+            "log msg=\"multi\nline, \\\"quoted\\\"\nstring\" 12345678\n",
+        ));
+        lines.push(String::from("c\"pu,\"k=\"v f=\"a,\\\"b\" 1\n"));
+        lines.push(String::from("\nmem free=7i"));
+        let input = lines.concat();
+        let expected = parse_points(&input).unwrap();
+
+        for step in [1, 3, 16, 17, 100, 4096] {
+            for read_size in [1, 7, 64, 1024] {
+                let reader = Trickle {
+                    data: input.as_bytes(),
+                    step,
+                };
+                let mut stream = StreamParser::new(reader).read_size(read_size);
+                let mut nodes = Vec::new();
+                while let Some(points) = stream.next_points().unwrap() {
+                    nodes.extend(
+                        points
+                            .into_nodes()
+                            .into_iter()
+                            .map(|node| format!("{node:?}")),
+                    );
+                }
+                let expected: Vec<_> = expected
+                    .nodes()
+                    .iter()
+                    .map(|node| format!("{node:?}"))
+                    .collect();
+                assert_eq!(nodes, expected, "step {step}, read size {read_size}");
+            }
+        }
+
+        // Batches only ever hold complete lines
+        let reader = Trickle {
+            data: b"cpu usage=0.5\nmem free=7i\nmem free=8i",
+            step: 20,
+        };
+        let mut stream = StreamParser::new(reader).read_size(20);
+        assert_eq!(stream.next_lines().unwrap(), Some("cpu usage=0.5\n"));
+        assert_eq!(stream.next_lines().unwrap(), Some("mem free=7i\n"));
+        assert_eq!(stream.next_lines().unwrap(), Some("mem free=8i"));
+        assert_eq!(stream.next_lines().unwrap(), None);
+    }
+
+    #[test]
+    fn stream_parser_errors() {
+        let reader = Trickle {
+            data: b"mem free=7i\nmem free=8i 12345678 9\n",
+            step: 12,
+        };
+        let mut stream = StreamParser::new(reader).read_size(12);
+        assert_eq!(stream.next_points().unwrap().unwrap().len(), 1);
+        match stream.next_points() {
+            Err(StreamError::Parse(err)) => {
+                assert_eq!(err.kind, ParseErrorKind::UnexpectedSeparator(' '));
+                assert_eq!((err.offset, err.line, err.column), (32, 2, 21));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let long = format!("mem free=7i\nmem {}=1i\n", "x".repeat(100));
+        let mut stream = StreamParser::new(long.as_bytes())
+            .read_size(16)
+            .max_line_len(64);
+        assert_eq!(stream.next_lines().unwrap(), Some("mem free=7i\n"));
+        assert!(matches!(
+            stream.next_lines(),
+            Err(StreamError::LineTooLong {
+                offset: 12,
+                max_line_len: 64
+            })
+        ));
+
+        let mut stream = StreamParser::new(&b"mem free=7i\nmem f\xffree=7i\n"[..]);
+        assert_eq!(stream.next_lines().unwrap(), Some("mem free=7i\n"));
+        assert!(matches!(
+            stream.next_lines(),
+            Err(StreamError::InvalidUtf8 { offset: 17 })
+        ));
+    }
+
+    /// Hands out one chunk per read with a `WouldBlock` before each, like a
+    /// nonblocking socket that goes quiet in between.
+    struct Quiet<'a> {
+        chunks: std::slice::Iter<'a, &'a str>,
+        ready: bool,
+    }
+
+    impl std::io::Read for Quiet<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.ready = !self.ready;
+            if self.ready {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            let chunk = self
+                .chunks
+                .next()
+                .map_or(&b""[..], |chunk| chunk.as_bytes());
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn stream_parser_quiet_reader() {
+        // Lines shorter than a chunk come out before the reader blocks, and
+        // a newline in a string still does not end one
+        let chunks = ["cpu s=\"a\nb\"\n", "mem f=2i\nmem", " f=3i\n"];
+        let reader = Quiet {
+            chunks: chunks.iter(),
+            ready: true,
+        };
+        let would_block = |result: Result<_, StreamError>| matches!(result, Err(StreamError::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock);
+        let mut stream = StreamParser::new(reader);
+        assert_eq!(stream.next_lines().unwrap(), Some("cpu s=\"a\nb\"\n"));
+        assert!(would_block(stream.next_lines().map(|_| ())));
+        let points = stream.next_points().unwrap().unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points.get(0).unwrap().field("f"), Some(&FieldValue::Int(2)));
+        assert!(would_block(stream.next_points().map(|_| ())));
+        assert_eq!(stream.next_lines().unwrap(), Some("mem f=3i\n"));
+        assert!(would_block(stream.next_lines().map(|_| ())));
+        assert_eq!(stream.next_lines().unwrap(), None);
+    }
+
+    /// Render a tape with a default [`LineWriter`].
+    fn write_nodes(nodes: &[Node]) -> std::io::Result<String> {
+        let mut writer = LineWriter::new(Vec::new());
+        writer.write_nodes(nodes)?;
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn line_writer() {
+        // parse(write(p)) == p, and the corpus is written just like gen_line
+        // writes it
+        let input: String = (0..2000).map(|_| gen_line()).collect();
+        let nodes = parse(&input).unwrap();
+        let written = write_nodes(&nodes).unwrap();
+        assert_eq!(written, input);
+        assert_eq!(parse(&written).unwrap(), nodes);
+
+        let points = parse_points(&input).unwrap();
+        let mut writer = LineWriter::new(Vec::new());
+        for point in points.iter() {
+            writer.write_point(&point).unwrap();
+        }
+        assert_eq!(writer.get_ref(), input.as_bytes());
+
+        // Every field type, and floats that take an exponent or many digits
+        let nodes = vec![
+            Node::Measurement("cpu".into()),
+            Node::Field {
+                key: "i".into(),
+                value: FieldValue::Int(i64::MIN),
+            },
+            Node::Field {
+                key: "u".into(),
+                value: FieldValue::UInt(u64::MAX),
+            },
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Float(-1.5e300),
+            },
+            Node::Field {
+                key: "g".into(),
+                value: FieldValue::Float(0.1 + 0.2),
+            },
+            Node::Field {
+                key: "h".into(),
+                value: FieldValue::Float(5e-324),
+            },
+            Node::Field {
+                key: "s".into(),
+                value: FieldValue::String("a \"q\"\\ \\n, =\nb".into()),
+            },
+            Node::Field {
+                key: "b".into(),
+                value: FieldValue::Bool(false),
+            },
+            Node::Timestamp(-5),
+        ];
+        let written = write_nodes(&nodes).unwrap();
+        assert_eq!(
+            written,
+            "cpu i=-9223372036854775808i,u=18446744073709551615u,f=-1.5e300,\
+             g=0.30000000000000004,h=5e-324,s=\"a \\\"q\\\"\\\\ \\\\n, =\nb\",b=false -5\n"
+        );
+        assert_eq!(parse(&written).unwrap(), nodes);
+
+        // Identifiers made of the bytes that need escaping either round-trip
+        // or are refused, never written to mean something else
+        const FRAGMENTS: &[&str] = &["a", " ", ",", "=", "\\", "\"", "\n", "\u{E9}"];
+        let mut rng = rand::thread_rng();
+        let mut random = || -> String {
+            (0..rng.gen_range(0..6))
+                .map(|_| FRAGMENTS[rng.gen_range(0..FRAGMENTS.len())])
+                .collect()
+        };
+        for _ in 0..5000 {
+            let nodes = vec![
+                Node::Measurement(random().into()),
+                Node::Tag {
+                    key: random().into(),
+                    value: random().into(),
+                },
+                Node::Field {
+                    key: random().into(),
+                    value: FieldValue::String(random().into()),
+                },
+            ];
+            let plain = nodes.iter().all(|node| match node {
+                Node::Measurement(text) => !text.is_empty() && !text.contains(['\\', '\n']),
+                Node::Tag { key, value } => [key, value]
+                    .iter()
+                    .all(|text| !text.is_empty() && !text.contains(['\\', '\n'])),
+                Node::Field { key, .. } => !key.is_empty() && !key.contains(['\\', '\n']),
+                Node::Timestamp(_) => true,
+            });
+            match write_nodes(&nodes) {
+                Ok(written) => assert_eq!(parse(&written), Ok(nodes), "{written:?}"),
+                Err(err) => {
+                    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+                    assert!(!plain, "{nodes:?}: {err}");
+                }
+            }
+        }
+
+        for (nodes, problem) in [
+            (
+                vec![Node::Measurement("cpu".into())],
+                "a point has no fields",
+            ),
+            (
+                vec![
+                    Node::Measurement("cpu".into()),
+                    Node::Field {
+                        key: "f".into(),
+                        value: FieldValue::Float(f64::NAN),
+                    },
+                ],
+                "float field value is not finite",
+            ),
+            (
+                vec![
+                    Node::Measurement("cpu".into()),
+                    Node::Tag {
+                        key: "a".into(),
+                        value: "b\\".into(),
+                    },
+                    Node::Field {
+                        key: "f".into(),
+                        value: FieldValue::Bool(true),
+                    },
+                ],
+                "tag value ends in an odd number of backslashes",
+            ),
+        ] {
+            let err = write_nodes(&nodes).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("cannot write line protocol: {problem}")
+            );
+        }
+
+        // Nothing of a refused line is written
+        let mut writer = LineWriter::new(Vec::new());
+        let nodes = parse("cpu f=1i\nmem f=2i\n").unwrap();
+        writer.write_nodes(&nodes).unwrap();
+        let refused = vec![
+            Node::Measurement("cpu".into()),
+            Node::Tag {
+                key: "host".into(),
+                value: "a\nb".into(),
+            },
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Int(1),
+            },
+        ];
+        assert!(writer.write_nodes(&refused).is_err());
+        assert_eq!(writer.get_ref(), b"cpu f=1i\nmem f=2i\n");
+    }
+
+    #[test]
+    fn line_writer_precision() {
+        let nodes = Parser::new()
+            .output_precision(Precision::Microseconds)
+            .parse("cpu f=1i 1700000000123456789\ncpu f=1i -1\n")
+            .unwrap()
+            .nodes;
+        assert_eq!(nodes[2], Node::Timestamp(1700000000123456));
+
+        let write = |precision| {
+            let mut writer = LineWriter::new(Vec::new())
+                .point_precision(Precision::Microseconds)
+                .precision(precision);
+            writer.write_nodes(&nodes).map(|()| writer.into_inner())
+        };
+        assert_eq!(
+            write(Precision::Nanoseconds).unwrap(),
+            b"cpu f=1i 1700000000123456000\ncpu f=1i -1000\n"
+        );
+        assert_eq!(
+            write(Precision::Microseconds).unwrap(),
+            b"cpu f=1i 1700000000123456\ncpu f=1i -1\n"
+        );
+        assert_eq!(
+            write(Precision::Seconds).unwrap(),
+            b"cpu f=1i 1700000000\ncpu f=1i -1\n"
+        );
+
+        // Written back in a precision the parser is told about, the point
+        // comes out the same
+        let written = write(Precision::Milliseconds).unwrap();
+        let parsed = Parser::new()
+            .precision(Precision::Milliseconds)
+            .output_precision(Precision::Microseconds)
+            .parse(std::str::from_utf8(&written).unwrap())
+            .unwrap();
+        assert_eq!(parsed.nodes[2], Node::Timestamp(1700000000123000));
+
+        let nodes = [
+            Node::Measurement("cpu".into()),
+            Node::Field {
+                key: "f".into(),
+                value: FieldValue::Bool(true),
+            },
+            Node::Timestamp(i64::MAX),
+        ];
+        let mut writer = LineWriter::new(Vec::new()).precision(Precision::Seconds);
+        writer.write_nodes(&nodes).unwrap();
+        let mut writer = LineWriter::new(Vec::new())
+            .point_precision(Precision::Seconds)
+            .precision(Precision::Nanoseconds);
+        assert!(writer.write_nodes(&nodes).is_err());
+    }
+
+    /// Like `Trickle`, but only every other poll is ready.
+    #[cfg(feature = "async")]
+    struct AsyncTrickle<'a> {
+        data: &'a [u8],
+        step: usize,
+        ready: bool,
+    }
+
+    #[cfg(feature = "async")]
+    impl tokio::io::AsyncRead for AsyncTrickle<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            let len = self.step.min(buf.remaining()).min(self.data.len());
+            buf.put_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn collect<R: tokio::io::AsyncRead + Unpin>(
+        mut stream: influx_parser::PointStream<R>,
+    ) -> Vec<Result<influx_parser::OwnedPoint, StreamError>> {
+        use futures_core::Stream;
+
+        let mut items = Vec::new();
+        while let Some(item) =
+            std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx)).await
+        {
+            items.push(item);
+        }
+        items
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn point_stream() {
+        use influx_parser::PointStream;
+
+        let mut lines: Vec<String> = (0..20).map(|_| gen_line()).collect();
+        lines.push(String::from(
+            "log msg=\"multi\nline, \\\"quoted\\\"\nstring\" 12345678\n",
+        ));
+        lines.push(String::from("\nmem free=7i"));
+        let input = lines.concat();
+        let expected = parse_points(&input).unwrap();
+
+        for step in [1, 3, 16, 17, 100, 4096] {
+            for read_size in [1, 7, 64, 1024] {
+                let reader = AsyncTrickle {
+                    data: input.as_bytes(),
+                    step,
+                    ready: false,
+                };
+                let points = collect(PointStream::new(reader).read_size(read_size)).await;
+                assert_eq!(points.len(), expected.len(), "{step} {read_size}");
+                for (point, expected) in points.iter().zip(expected.iter()) {
+                    assert_eq!(point.as_ref().unwrap().point(), expected);
+                }
+            }
+        }
+
+        // Points before the first error come through, nothing after it
+        let reader = AsyncTrickle {
+            data: b"mem free=7i\nmem free=8i 12345678 9\nmem free=9i\n",
+            step: 12,
+            ready: false,
+        };
+        let items = collect(PointStream::new(reader).read_size(12)).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap().point().measurement, "mem");
+        match &items[1] {
+            Err(StreamError::Parse(err)) => {
+                assert_eq!(err.kind, ParseErrorKind::UnexpectedSeparator(' '));
+                assert_eq!((err.offset, err.line, err.column), (32, 2, 21));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let long = format!("mem free=7i\nmem {}=1i\n", "x".repeat(100));
+        let stream = PointStream::new(long.as_bytes())
+            .read_size(16)
+            .max_line_len(64);
+        let items = collect(stream).await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(
+            items[1],
+            Err(StreamError::LineTooLong {
+                offset: 12,
+                max_line_len: 64
+            })
+        ));
+
+        let items = collect(PointStream::new(&b"mem free=7i\nmem f\xffree=7i\n"[..])).await;
+        assert!(matches!(
+            items[..],
+            [Ok(_), Err(StreamError::InvalidUtf8 { offset: 17 })]
+        ));
+        assert!(collect(PointStream::new(&b""[..])).await.is_empty());
+    }
+
+    /// Hands out `data` in one read, then stays pending like a quiet socket.
+    #[cfg(feature = "async")]
+    struct AsyncQuiet<'a> {
+        data: &'a [u8],
+    }
+
+    #[cfg(feature = "async")]
+    impl tokio::io::AsyncRead for AsyncQuiet<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if self.data.is_empty() {
+                return std::task::Poll::Pending;
+            }
+            buf.put_slice(self.data);
+            self.data = &[];
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn point_stream_quiet_reader() {
+        use futures_core::Stream;
+        use influx_parser::PointStream;
+        use std::task::{Context, Poll, Waker};
+
+        // A line shorter than a chunk comes out while the reader is pending,
+        // the partial line after it does not
+        let mut stream = PointStream::new(AsyncQuiet {
+            data: b"cpu f=1i\nmem f=",
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        match std::pin::Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok(point))) => {
+                assert_eq!(point.point().measurement, "cpu");
+                assert_eq!(point.point().field("f"), Some(&FieldValue::Int(1)));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(std::pin::Pin::new(&mut stream)
+            .poll_next(&mut cx)
+            .is_pending());
     }
 
     #[test]
@@ -75,20 +1997,20 @@ mod tests {
         assert_eq!(
             items,
             vec![
-                Node::Measurement("ab"),
+                Node::Measurement("ab".into()),
                 Node::Tag {
-                    key: "cd",
-                    value: "ef"
+                    key: "cd".into(),
+                    value: "ef".into()
                 },
                 Node::Field {
-                    key: "gh",
-                    value: 15
+                    key: "gh".into(),
+                    value: FieldValue::Int(15)
                 },
                 Node::Field {
-                    key: "jk",
-                    value: 16
+                    key: "jk".into(),
+                    value: FieldValue::Int(16)
                 },
-                Node::Timestamp(1234567)
+                Node::Timestamp(12345678)
             ]
         );
 
@@ -97,20 +2019,20 @@ mod tests {
         assert_eq!(
             items,
             vec![
-                Node::Measurement("ab"),
+                Node::Measurement("ab".into()),
                 Node::Tag {
-                    key: "cd",
-                    value: "ef"
+                    key: "cd".into(),
+                    value: "ef".into()
                 },
                 Node::Field {
-                    key: "gh",
-                    value: 15
+                    key: "gh".into(),
+                    value: FieldValue::Int(15)
                 },
                 Node::Field {
-                    key: "jk",
-                    value: 16
+                    key: "jk".into(),
+                    value: FieldValue::Int(16)
                 },
-                Node::Timestamp(1234567)
+                Node::Timestamp(12345678)
             ]
         );
 
@@ -119,16 +2041,16 @@ mod tests {
         assert_eq!(
             items,
             vec![
-                Node::Measurement("ab"),
+                Node::Measurement("ab".into()),
                 Node::Field {
-                    key: "gh",
-                    value: 15
+                    key: "gh".into(),
+                    value: FieldValue::Int(15)
                 },
                 Node::Field {
-                    key: "jk",
-                    value: 16
+                    key: "jk".into(),
+                    value: FieldValue::Int(16)
                 },
-                Node::Timestamp(1234567)
+                Node::Timestamp(12345678)
             ]
         );
     }
@@ -0,0 +1,146 @@
+//! Decimal digit decoding for numeric field values and timestamps.
+//!
+//! Timestamps are 10 to 19 digits long and make up a good part of every line,
+//! so instead of folding in one byte at a time, runs of 16 digits go through
+//! SSE4.1 and runs of 8 through SWAR arithmetic on a `u64`.
+
+use std::sync::OnceLock;
+
+/// Decodes exactly 16 ASCII digits, see [`parse_16_digits`].
+type Parse16Fn = fn(&[u8; 16]) -> Option<u64>;
+
+static PARSE_16_DIGITS: OnceLock<Parse16Fn> = OnceLock::new();
+
+/// Decode a run of ASCII digits into a `u64`. Returns `None` when `digits` is
+/// empty, contains anything but `0-9` or overflows.
+pub(crate) fn parse_digits(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    // u64::MAX has 20 digits, longer runs are rejected outright once leading
+    // zeros are out of the way
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+    let digits = &digits[zeros..];
+    if digits.len() > 20 {
+        return None;
+    }
+
+    let mut value: u64 = 0;
+    let mut rest = digits;
+
+    if let Some((chunk, tail)) = rest.split_first_chunk::<16>() {
+        value = parse_16_digits()(chunk)?;
+        rest = tail;
+    }
+
+    while let Some((chunk, tail)) = rest.split_first_chunk::<8>() {
+        value = value
+            .checked_mul(100_000_000)?
+            .checked_add(parse_8_digits(*chunk)?)?;
+        rest = tail;
+    }
+
+    rest.iter().try_fold(value, |a, &c| {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        a.checked_mul(10)?.checked_add((c & 0x0f) as u64)
+    })
+}
+
+/// Decode exactly 8 ASCII digits at once, treating them as one little endian
+/// `u64` so the first digit ends up in the lowest byte.
+pub(crate) fn parse_8_digits(chunk: [u8; 8]) -> Option<u64> {
+    const ZEROS: u64 = u64::from_ne_bytes([b'0'; 8]);
+    let val = u64::from_le_bytes(chunk);
+
+    // Every byte is 0x30-0x39: the high nibble is 3 and adding 6 to the low
+    // nibble does not carry into it
+    let high = val & 0xF0F0_F0F0_F0F0_F0F0;
+    let carried = (val.wrapping_add(0x0606_0606_0606_0606) & 0xF0F0_F0F0_F0F0_F0F0) >> 4;
+    if high | carried != 0x3333_3333_3333_3333 {
+        return None;
+    }
+
+    // Combine neighbouring digits, then pairs of those, then the two halves
+    let val = val - ZEROS;
+    let val = (val * 10) + (val >> 8);
+    let val = ((val & 0x0000_00FF_0000_00FF).wrapping_mul(100 + (1_000_000 << 32))
+        + ((val >> 16) & 0x0000_00FF_0000_00FF).wrapping_mul(1 + (10_000 << 32)))
+        >> 32;
+    Some(val)
+}
+
+/// The 16 digit decoder for this CPU, picked on first use so that the hot
+/// path does not check for SSE4.1 on every number.
+fn parse_16_digits() -> Parse16Fn {
+    *PARSE_16_DIGITS.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("sse4.1") {
+            return |chunk| unsafe { parse_16_digits_sse41(chunk) };
+        }
+        parse_16_digits_swar
+    })
+}
+
+/// Decode exactly 16 ASCII digits as two runs of 8.
+fn parse_16_digits_swar(chunk: &[u8; 16]) -> Option<u64> {
+    let (high, low) = chunk.split_at(8);
+    let high = parse_8_digits(high.try_into().unwrap())?;
+    let low = parse_8_digits(low.try_into().unwrap())?;
+    Some(high * 100_000_000 + low)
+}
+
+/// Decode exactly 16 ASCII digits.
+///
+/// # Safety
+///
+/// The caller must ensure the CPU supports SSE4.1.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn parse_16_digits_sse41(chunk: &[u8; 16]) -> Option<u64> {
+    use std::arch::x86_64::*;
+
+    let ascii = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+    let digits = _mm_sub_epi8(ascii, _mm_set1_epi8(b'0' as i8));
+    // Bytes below '0' wrapped around and are now above 9 as well
+    let valid = _mm_cmpeq_epi8(_mm_min_epu8(digits, _mm_set1_epi8(9)), digits);
+    if _mm_movemask_epi8(valid) != 0xFFFF {
+        return None;
+    }
+
+    // 16 digits -> 8 values below 100 -> 4 below 10^4 -> 2 below 10^8
+    let pairs = _mm_maddubs_epi16(digits, _mm_set1_epi16(0x010A));
+    let quads = _mm_madd_epi16(pairs, _mm_set1_epi32(0x0001_0064));
+    let quads = _mm_packus_epi32(quads, quads);
+    let octets = _mm_madd_epi16(quads, _mm_set1_epi32(0x0001_2710));
+
+    let high = _mm_cvtsi128_si32(octets) as u64;
+    let low = _mm_extract_epi32::<1>(octets) as u64;
+    Some(high * 100_000_000 + low)
+}
+
+/// Powers of ten that are exactly representable as an `f64`.
+const EXACT_POWERS_OF_TEN: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
+    1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+];
+
+/// Clinger's fast path: convert `mantissa * 10^exponent`, where the mantissa
+/// was read from `digits` decimal digits, when both the mantissa and the power
+/// of ten are exact as an `f64`. A single, correctly rounded multiplication or
+/// division then gives the correctly rounded result. Returns `None` when the
+/// value has to take the slow path.
+pub(crate) fn parse_decimal_fast(mantissa: u64, digits: usize, exponent: i64) -> Option<f64> {
+    // Up to 19 digits always fit a u64, more may have wrapped around
+    if digits > 19 || mantissa > 1 << f64::MANTISSA_DIGITS {
+        return None;
+    }
+
+    let power = *EXACT_POWERS_OF_TEN.get(exponent.unsigned_abs() as usize)?;
+    if exponent < 0 {
+        Some(mantissa as f64 / power)
+    } else {
+        Some(mantissa as f64 * power)
+    }
+}
@@ -0,0 +1,168 @@
+//! Parsing one large batch on several threads.
+//!
+//! The input is cut after newlines into chunks of about equal length, and
+//! every chunk is parsed on its own thread as if it were a batch of its own.
+//! A newline inside a multi-line string field does not end a line though, and
+//! where the chunks are cut is decided without knowing about strings. That is
+//! checked afterwards instead: a chunk was cut right if stage 1 was not inside
+//! a string when it reached the end of the previous one. From the first chunk
+//! where that does not hold, the rest of the input is parsed again on the
+//! calling thread.
+
+use std::thread;
+
+use crate::backend::ScanFn;
+use crate::{
+    build_tape, scanner, DefaultTimestamp, LineError, ParseError, Parsed, Parser, ScanState,
+};
+
+/// Chunks are at least this long, spawning a thread for less is not worth it.
+const MIN_CHUNK_LEN: usize = 256 * 1024;
+
+/// Parse `input` on up to `parser.threads` threads, with the same result as
+/// [`build_tape`] on one.
+pub(crate) fn parse<'a>(
+    input: &'a str,
+    scan: ScanFn,
+    index: &mut Vec<u32>,
+    parser: &Parser,
+) -> Result<Parsed<'a>, ParseError> {
+    let bounds = split(input.as_bytes(), parser.threads);
+    if bounds.len() <= 2 {
+        return build_tape(input, scan, index, parser, &mut ScanState::default());
+    }
+
+    // All chunks stamp lines without a timestamp with the same time
+    let mut parser = parser.clone();
+    parser.default_timestamp = parser
+        .default_timestamp
+        .map(|timestamp| DefaultTimestamp::Fixed(timestamp.get()));
+    let parser = &parser;
+
+    let results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = bounds[1..]
+            .windows(2)
+            .map(|chunk| {
+                let chunk = &input[chunk[0]..chunk[1]];
+                scope.spawn(move || {
+                    let mut state = ScanState::default();
+                    let parsed = build_tape(chunk, scan, &mut Vec::new(), parser, &mut state);
+                    (parsed, state)
+                })
+            })
+            .collect();
+
+        // The calling thread takes the first chunk
+        let mut state = ScanState::default();
+        let first = build_tape(&input[..bounds[1]], scan, index, parser, &mut state);
+        std::iter::once((first, state))
+            .chain(workers.into_iter().map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            }))
+            .collect()
+    });
+
+    let nodes = results
+        .iter()
+        .filter_map(|(parsed, _)| parsed.as_ref().ok())
+        .map(|parsed| parsed.nodes.len())
+        .sum();
+    let mut merged = Parsed {
+        nodes: Vec::with_capacity(nodes),
+        errors: Vec::new(),
+    };
+    for ((parsed, state), &start) in results.into_iter().zip(&bounds) {
+        // An error may come from the last line being cut short, and a chunk
+        // that ends inside a string was cut short. Either way the rest is
+        // parsed again in one piece to get it right.
+        if parsed.is_err() || state.prev_in_string != 0 {
+            let rest = build_tape(
+                &input[start..],
+                scan,
+                index,
+                parser,
+                &mut ScanState::default(),
+            );
+            append(&mut merged, rest, input, start)?;
+            break;
+        }
+        append(&mut merged, parsed, input, start)?;
+    }
+
+    Ok(merged)
+}
+
+/// Add the result of parsing the chunk starting at `start` to `merged`,
+/// moving error positions from the chunk to the whole input.
+fn append<'a>(
+    merged: &mut Parsed<'a>,
+    parsed: Result<Parsed<'a>, ParseError>,
+    input: &str,
+    start: usize,
+) -> Result<(), ParseError> {
+    // Counting lines is only needed for errors, which are rare
+    let lines = || {
+        input.as_bytes()[..start]
+            .iter()
+            .filter(|&&c| c == b'\n')
+            .count()
+    };
+    let shift = |err: &mut ParseError, lines: usize| {
+        err.offset += start;
+        err.line += lines;
+    };
+
+    match parsed {
+        Ok(parsed) => {
+            merged.nodes.extend(parsed.nodes);
+            if !parsed.errors.is_empty() {
+                let lines = lines();
+                merged
+                    .errors
+                    .extend(parsed.errors.into_iter().map(|mut line_error| {
+                        shift(&mut line_error.error, lines);
+                        LineError {
+                            span: line_error.span.start + start..line_error.span.end + start,
+                            error: line_error.error,
+                        }
+                    }));
+            }
+            Ok(())
+        }
+        Err(mut err) => {
+            shift(&mut err, lines());
+            Err(err)
+        }
+    }
+}
+
+/// Split `bytes` into up to `parts` chunks of about the same length, each
+/// starting right after a newline. Returns where every chunk starts, followed
+/// by the end of the last.
+fn split(bytes: &[u8], parts: usize) -> Vec<usize> {
+    let parts = parts.min(bytes.len() / MIN_CHUNK_LEN).max(1);
+    let mut bounds = vec![0];
+    for part in 1..parts {
+        let target = (bytes.len() / parts * part).max(bounds[bounds.len() - 1]);
+        match next_line(bytes, target) {
+            Some(start) if start < bytes.len() => bounds.push(start),
+            _ => break,
+        }
+    }
+    bounds.push(bytes.len());
+    bounds
+}
+
+/// Start of the first line after `from`. A newline right after a backslash
+/// might be escaped, so it is skipped rather than worked out.
+fn next_line(bytes: &[u8], mut from: usize) -> Option<usize> {
+    loop {
+        let newline = from + scanner::find_newline(&bytes[from..])?;
+        if newline == 0 || bytes[newline - 1] != b'\\' {
+            return Some(newline + 1);
+        }
+        from = newline + 1;
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{FieldValue, Node};
+
+/// One line of line protocol, borrowing its tags and fields from the node
+/// arena of a [`Points`] batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point<'a> {
+    pub measurement: &'a str,
+    /// Only [`Node::Tag`]s.
+    pub tags: &'a [Node<'a>],
+    /// Only [`Node::Field`]s, never empty.
+    pub fields: &'a [Node<'a>],
+    pub timestamp: Option<i64>,
+}
+
+impl<'a> Point<'a> {
+    /// Value of the tag `key`, if the point has it.
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.tags.iter().find_map(|node| match node {
+            Node::Tag { key: k, value } if k == key => Some(value.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Value of the field `key`, if the point has it.
+    pub fn field(&self, key: &str) -> Option<&'a FieldValue<'a>> {
+        self.fields.iter().find_map(|node| match node {
+            Node::Field { key: k, value } if k == key => Some(value),
+            _ => None,
+        })
+    }
+}
+
+/// Where the nodes of one point live in the arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PointRange {
+    measurement: usize,
+    fields_start: usize,
+    fields_end: usize,
+    timestamp: Option<i64>,
+}
+
+impl PointRange {
+    fn new(measurement: usize) -> Self {
+        PointRange {
+            measurement,
+            fields_start: measurement + 1,
+            fields_end: measurement + 1,
+            timestamp: None,
+        }
+    }
+
+    /// Account for the node at `idx`, which follows the measurement.
+    fn push(&mut self, idx: usize, node: &Node) {
+        match node {
+            // Tags always come before the fields
+            Node::Tag { .. } => {
+                self.fields_start = idx + 1;
+                self.fields_end = idx + 1;
+            }
+            Node::Field { .. } => self.fields_end = idx + 1,
+            Node::Timestamp(timestamp) => self.timestamp = Some(*timestamp),
+            Node::Measurement(_) => unreachable!(),
+        }
+    }
+
+    /// Whether `node` may follow the nodes so far in a well formed point:
+    /// tags, then at least one field, then at most one timestamp.
+    fn takes(&self, node: &Node) -> bool {
+        match node {
+            Node::Measurement(_) => false,
+            Node::Tag { .. } => !self.has_fields() && self.timestamp.is_none(),
+            Node::Field { .. } => self.timestamp.is_none(),
+            Node::Timestamp(_) => self.has_fields() && self.timestamp.is_none(),
+        }
+    }
+
+    fn has_fields(&self) -> bool {
+        self.fields_start < self.fields_end
+    }
+
+    fn point<'a>(&self, nodes: &'a [Node<'a>]) -> Point<'a> {
+        let measurement = match &nodes[self.measurement] {
+            Node::Measurement(measurement) => measurement.as_ref(),
+            _ => unreachable!(),
+        };
+        Point {
+            measurement,
+            tags: &nodes[self.measurement + 1..self.fields_start],
+            fields: &nodes[self.fields_start..self.fields_end],
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// A parsed batch, grouped into points.
+///
+/// All nodes stay in the single flat tape produced by the parser; each point
+/// is only a few indices into it, so a batch costs two allocations no matter
+/// how many points it holds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Points<'input> {
+    nodes: Vec<Node<'input>>,
+    ranges: Vec<PointRange>,
+}
+
+impl<'input> Points<'input> {
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<Point<'_>> {
+        self.ranges.get(idx).map(|range| range.point(&self.nodes))
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Point<'_>> + '_ {
+        self.ranges.iter().map(|range| range.point(&self.nodes))
+    }
+
+    /// The underlying flat tape.
+    pub fn nodes(&self) -> &[Node<'input>] {
+        &self.nodes
+    }
+
+    pub fn into_nodes(self) -> Vec<Node<'input>> {
+        self.nodes
+    }
+}
+
+/// Where the points of `nodes` are, or `None` when it is not a well formed
+/// tape.
+fn point_ranges(nodes: &[Node]) -> Option<Vec<PointRange>> {
+    let mut ranges: Vec<PointRange> = Vec::new();
+    for (idx, node) in nodes.iter().enumerate() {
+        match ranges.last_mut() {
+            Some(range) if range.takes(node) => range.push(idx, node),
+            Some(range) if !range.has_fields() => return None,
+            _ if matches!(node, Node::Measurement(_)) => ranges.push(PointRange::new(idx)),
+            _ => return None,
+        }
+    }
+    match ranges.last() {
+        Some(range) if !range.has_fields() => None,
+        _ => Some(ranges),
+    }
+}
+
+impl<'input> Points<'input> {
+    /// Group a tape the parser just built, which is always well formed.
+    pub(crate) fn from_tape(nodes: Vec<Node<'input>>) -> Self {
+        let ranges = point_ranges(&nodes).expect("the parser builds well formed tapes");
+        Points { nodes, ranges }
+    }
+}
+
+/// Group a tape into points, e.g. the nodes of a lenient
+/// [`Parser`](crate::Parser). A tape that is not a series of points, each a
+/// measurement, its tags, at least one field and an optional timestamp, is
+/// handed back as the error.
+///
+/// ```
+/// use influx_parser::{Node, Points};
+///
+/// let nodes = vec![Node::Measurement("cpu".into()), Node::Timestamp(1)];
+/// assert_eq!(Points::try_from(nodes.clone()), Err(nodes));
+/// ```
+impl<'input> TryFrom<Vec<Node<'input>>> for Points<'input> {
+    type Error = Vec<Node<'input>>;
+
+    fn try_from(nodes: Vec<Node<'input>>) -> Result<Self, Self::Error> {
+        match point_ranges(&nodes) {
+            Some(ranges) => Ok(Points { nodes, ranges }),
+            None => Err(nodes),
+        }
+    }
+}
+
+/// The points of a well formed tape, without copying it into [`Points`].
+pub(crate) fn points_of<'a>(nodes: &'a [Node<'a>]) -> impl Iterator<Item = Point<'a>> {
+    let mut starts = nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| matches!(node, Node::Measurement(_)))
+        .map(|(idx, _)| idx)
+        .peekable();
+    std::iter::from_fn(move || {
+        let start = starts.next()?;
+        let end = starts.peek().copied().unwrap_or(nodes.len());
+        let mut range = PointRange::new(start);
+        for (idx, node) in nodes.iter().enumerate().take(end).skip(start + 1) {
+            range.push(idx, node);
+        }
+        Some(range.point(nodes))
+    })
+}
+
+/// A single point that owns its nodes, as yielded by
+/// [`PointIter`](crate::PointIter).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointBuf<'input> {
+    nodes: Vec<Node<'input>>,
+    range: PointRange,
+}
+
+impl<'input> PointBuf<'input> {
+    pub fn point(&self) -> Point<'_> {
+        self.range.point(&self.nodes)
+    }
+
+    pub fn nodes(&self) -> &[Node<'input>] {
+        &self.nodes
+    }
+
+    pub fn into_nodes(self) -> Vec<Node<'input>> {
+        self.nodes
+    }
+
+    /// Copy out everything borrowed from the input, see [`OwnedPoint`].
+    pub fn into_owned(self) -> OwnedPoint {
+        PointBuf {
+            nodes: self.nodes.into_iter().map(Node::into_owned).collect(),
+            range: self.range,
+        }
+    }
+}
+
+/// A point that does not borrow the input, so it can outlive the buffer it
+/// was parsed from.
+pub type OwnedPoint = PointBuf<'static>;
+
+impl<'input> PointBuf<'input> {
+    /// Wrap the nodes of a line the parser just built, which is always well
+    /// formed.
+    pub(crate) fn from_line(nodes: Vec<Node<'input>>) -> Self {
+        let mut range = PointRange::new(0);
+        for (idx, node) in nodes.iter().enumerate().skip(1) {
+            range.push(idx, node);
+        }
+        PointBuf { nodes, range }
+    }
+}
+
+/// Wrap the nodes of a single point, handing them back as the error unless
+/// they are exactly one well formed point, see [`Points`].
+impl<'input> TryFrom<Vec<Node<'input>>> for PointBuf<'input> {
+    type Error = Vec<Node<'input>>;
+
+    fn try_from(nodes: Vec<Node<'input>>) -> Result<Self, Self::Error> {
+        match point_ranges(&nodes).as_deref() {
+            Some(&[range]) => Ok(PointBuf { nodes, range }),
+            _ => Err(nodes),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+/// The unit of a line protocol timestamp, as in the `precision` parameter of
+/// an InfluxDB write. The line protocol itself defaults to nanoseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Precision {
+    #[default]
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Precision {
+    /// Length of one unit in nanoseconds.
+    pub fn nanos(self) -> i64 {
+        match self {
+            Precision::Nanoseconds => 1,
+            Precision::Microseconds => 1_000,
+            Precision::Milliseconds => 1_000_000,
+            Precision::Seconds => 1_000_000_000,
+        }
+    }
+
+    /// Convert `timestamp` from this precision to `to`. Going to a coarser
+    /// precision rounds towards negative infinity, so a time before the epoch
+    /// stays before it. Returns `None` when the result does not fit an `i64`.
+    ///
+    /// ```
+    /// use influx_parser::Precision;
+    ///
+    /// let ms = Precision::Milliseconds;
+    /// assert_eq!(ms.convert(1_500, Precision::Nanoseconds), Some(1_500_000_000));
+    /// assert_eq!(ms.convert(1_500, Precision::Seconds), Some(1));
+    /// assert_eq!(ms.convert(-1_500, Precision::Seconds), Some(-2));
+    /// assert_eq!(ms.convert(i64::MAX, Precision::Microseconds), None);
+    /// ```
+    pub fn convert(self, timestamp: i64, to: Precision) -> Option<i64> {
+        // All units are powers of 1000 apart, so one always divides the other
+        if self.nanos() >= to.nanos() {
+            timestamp.checked_mul(self.nanos() / to.nanos())
+        } else {
+            Some(timestamp.div_euclid(to.nanos() / self.nanos()))
+        }
+    }
+
+    /// The short name InfluxDB uses for the `precision` parameter.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precision::Nanoseconds => "ns",
+            Precision::Microseconds => "us",
+            Precision::Milliseconds => "ms",
+            Precision::Seconds => "s",
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! A plain scalar line protocol parser, written for clarity rather than
+//! speed.
+//!
+//! It walks the input one byte at a time with a cursor and follows the
+//! grammar top down, without a stage 1 or a state machine, and decodes values
+//! with the standard library. It gives the same nodes and the same errors as
+//! the SIMD paths with the default [`Parser`](crate::Parser) options, which
+//! makes it an oracle to test them against.
+
+use crate::{unescape_identifier, unescape_string, FieldValue, Node, ParseError, ParseErrorKind};
+
+/// Parse `input`, stopping at the first malformed line.
+///
+/// ```
+/// use influx_parser::{parse, reference};
+///
+/// let input = "cpu,host=a usage=0.5,msg=\"x y\" 1000\nmem free=7i\n";
+/// assert_eq!(reference::parse(input), parse(input));
+/// ```
+pub fn parse(input: &str) -> Result<Vec<Node<'_>>, ParseError> {
+    let mut cursor = Cursor {
+        input,
+        pos: 0,
+        line_start: 0,
+    };
+    let mut nodes: Vec<Node> = Vec::new();
+
+    while cursor.pos <= input.len() {
+        cursor.line_start = cursor.pos;
+        cursor.line(&mut nodes)?;
+    }
+
+    Ok(nodes)
+}
+
+/// What ends an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    Space,
+    Comma,
+    Equals,
+    /// A newline, a `\0` or the end of the input.
+    Newline,
+}
+
+struct Cursor<'a> {
+    input: &'a str,
+    // Start of the next item
+    pos: usize,
+    line_start: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Read up to the next separator that is not escaped by a backslash, and
+    /// move past it. Quotes are plain bytes here, only a field value can be a
+    /// string.
+    fn item(&mut self) -> (&'a str, usize, Separator) {
+        self.item_from(self.pos, false)
+    }
+
+    /// Like [`item`](Self::item), but a quote at the start opens a string
+    /// that separators inside do not end, up to the next unescaped quote.
+    fn field_value_item(&mut self) -> (&'a str, usize, Separator) {
+        let quoted = self.input.as_bytes().get(self.pos) == Some(&b'"');
+        self.item_from(self.pos + quoted as usize, quoted)
+    }
+
+    /// Read the item starting at `self.pos`, scanning from `from` on and
+    /// starting out `in_string` or not.
+    fn item_from(&mut self, from: usize, mut in_string: bool) -> (&'a str, usize, Separator) {
+        let bytes = self.input.as_bytes();
+        let start = self.pos;
+        let mut idx = from;
+
+        let separator = loop {
+            let Some(&c) = bytes.get(idx) else {
+                break Separator::Newline;
+            };
+            match c {
+                b'\\' => {
+                    // Skip whatever is escaped
+                    idx += 2;
+                    continue;
+                }
+                b'"' if in_string => in_string = false,
+                _ if in_string => {}
+                b' ' => break Separator::Space,
+                b',' => break Separator::Comma,
+                b'=' => break Separator::Equals,
+                b'\n' | 0x00 => break Separator::Newline,
+                _ => {}
+            }
+            idx += 1;
+        };
+
+        // A backslash at the very end skips past the input
+        let end = idx.min(bytes.len());
+        self.pos = end + 1;
+        (&self.input[start..end], end, separator)
+    }
+
+    /// Parsing stops at the first error, so it may as well count its line
+    /// from the start.
+    fn error(&self, kind: ParseErrorKind, start: usize, end: usize) -> ParseError {
+        ParseError::new(kind, self.input, 1, 0, start, end)
+    }
+
+    /// Error on the separator at `offset`.
+    fn unexpected(&self, separator: Separator, offset: usize) -> ParseError {
+        let ch = match separator {
+            Separator::Space => ' ',
+            Separator::Comma => ',',
+            Separator::Equals => '=',
+            Separator::Newline => unreachable!(),
+        };
+        self.error(ParseErrorKind::UnexpectedSeparator(ch), offset, offset + 1)
+    }
+
+    fn missing_field_set(&self, offset: usize) -> ParseError {
+        self.error(ParseErrorKind::MissingFieldSet, self.line_start, offset)
+    }
+
+    /// `measurement[,tag=value...] field=value[,field=value...] [timestamp]`
+    fn line(&mut self, nodes: &mut Vec<Node<'a>>) -> Result<(), ParseError> {
+        let (measurement, offset, separator) = self.item();
+        match separator {
+            Separator::Newline if measurement.is_empty() => return Ok(()),
+            Separator::Newline => return Err(self.missing_field_set(offset)),
+            Separator::Equals => return Err(self.unexpected(separator, offset)),
+            _ if measurement.is_empty() => {
+                return Err(self.error(ParseErrorKind::EmptyMeasurement, offset, offset + 1));
+            }
+            _ => {}
+        }
+        nodes.push(Node::Measurement(unescape_identifier(measurement)));
+
+        if separator == Separator::Comma {
+            self.tag_set(nodes)?;
+        }
+        if self.field_set(nodes)? {
+            self.timestamp(nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Check the key just read, which ended in `=` at `offset`.
+    fn key(&self, key: &'a str, offset: usize) -> Result<&'a str, ParseError> {
+        if key.is_empty() {
+            return Err(self.error(ParseErrorKind::EmptyKey, offset, offset + 1));
+        }
+        Ok(key)
+    }
+
+    fn tag_set(&mut self, nodes: &mut Vec<Node<'a>>) -> Result<(), ParseError> {
+        loop {
+            let key_start = self.pos;
+            let (key, offset, separator) = self.item();
+            let key = match separator {
+                Separator::Equals => self.key(key, offset)?,
+                Separator::Newline => return Err(self.missing_field_set(offset)),
+                _ => return Err(self.error(ParseErrorKind::MissingValue, key_start, offset)),
+            };
+
+            let (value, offset, separator) = self.item();
+            match separator {
+                Separator::Newline => return Err(self.missing_field_set(offset)),
+                Separator::Equals => return Err(self.unexpected(separator, offset)),
+                _ if value.is_empty() => {
+                    return Err(self.error(ParseErrorKind::EmptyTagValue, offset, offset + 1));
+                }
+                _ => {}
+            }
+            nodes.push(Node::Tag {
+                key: unescape_identifier(key),
+                value: unescape_identifier(value),
+            });
+
+            if separator == Separator::Space {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns whether a timestamp may follow.
+    fn field_set(&mut self, nodes: &mut Vec<Node<'a>>) -> Result<bool, ParseError> {
+        let mut first = true;
+        loop {
+            let key_start = self.pos;
+            let (key, offset, separator) = self.item();
+            if separator != Separator::Equals {
+                // Nothing at all after the measurement or tags is a missing
+                // field set, anything else a dangling key
+                let kind = if first && key.is_empty() {
+                    ParseErrorKind::MissingFieldSet
+                } else {
+                    ParseErrorKind::MissingValue
+                };
+                return Err(self.error(kind, key_start, offset));
+            }
+            let key = self.key(key, offset)?;
+            first = false;
+
+            let value_start = self.pos;
+            let (value, offset, separator) = self.field_value_item();
+            if separator == Separator::Equals {
+                return Err(self.unexpected(separator, offset));
+            }
+            let value = field_value(value).map_err(|kind| self.error(kind, value_start, offset))?;
+            nodes.push(Node::Field {
+                key: unescape_identifier(key),
+                value,
+            });
+
+            match separator {
+                Separator::Comma => {}
+                Separator::Space => return Ok(true),
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    fn timestamp(&mut self, nodes: &mut Vec<Node<'a>>) -> Result<(), ParseError> {
+        let start = self.pos;
+        let (timestamp, offset, separator) = self.item();
+        if separator != Separator::Newline {
+            return Err(self.unexpected(separator, offset));
+        }
+        // A trailing space is not a timestamp
+        if timestamp.is_empty() {
+            return Ok(());
+        }
+
+        let timestamp = integer::<i64>(timestamp)
+            .ok_or_else(|| self.error(ParseErrorKind::InvalidTimestamp, start, offset))?;
+        nodes.push(Node::Timestamp(timestamp));
+        Ok(())
+    }
+}
+
+/// The type of a field value follows from its last byte, or its quotes.
+fn field_value(value: &str) -> Result<FieldValue<'_>, ParseErrorKind> {
+    let bytes = value.as_bytes();
+    let last = bytes.last().copied().unwrap_or(0);
+    let before_last = bytes.len().checked_sub(2).map(|idx| bytes[idx]);
+    let digit_before_last = before_last.is_some_and(|c| c.is_ascii_digit());
+
+    if bytes.len() >= 2 && bytes[0] == b'"' && last == b'"' {
+        Ok(FieldValue::String(unescape_string(
+            &value[1..value.len() - 1],
+        )))
+    } else if last == b'i' && digit_before_last {
+        integer(&value[..value.len() - 1])
+            .map(FieldValue::Int)
+            .ok_or(ParseErrorKind::InvalidInteger)
+    } else if last == b'u' && digit_before_last {
+        integer(&value[..value.len() - 1])
+            .map(FieldValue::UInt)
+            .ok_or(ParseErrorKind::InvalidUnsigned)
+    } else if last.is_ascii_digit() || last == b'.' {
+        float(value)
+            .map(FieldValue::Float)
+            .ok_or(ParseErrorKind::InvalidFloat)
+    } else {
+        match value {
+            "t" | "T" | "true" | "True" | "TRUE" => Ok(FieldValue::Bool(true)),
+            "f" | "F" | "false" | "False" | "FALSE" => Ok(FieldValue::Bool(false)),
+            _ => Err(ParseErrorKind::InvalidFieldValue),
+        }
+    }
+}
+
+/// The standard library also takes a leading `+`, the line protocol does not.
+fn integer<T: std::str::FromStr>(digits: &str) -> Option<T> {
+    if digits.starts_with('+') {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// `[-]digits[.digits][(e|E)[+|-]digits]`, which the standard library accepts
+/// along with `inf`, `NaN` and a leading `+`.
+fn float(value: &str) -> Option<f64> {
+    let well_formed = !value.starts_with('+')
+        && value
+            .bytes()
+            .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'-' | b'+' | b'e' | b'E'));
+    if !well_formed {
+        return None;
+    }
+    value.parse::<f64>().ok().filter(|value| value.is_finite())
+}
@@ -0,0 +1,647 @@
+//! Stage 1 scanners, generic over the number of bytes classified at once.
+//!
+//! A [`StructuralScanner`] only has to say which class each byte of a chunk
+//! is in. Masking out escaped bytes and bytes inside quoted strings, and
+//! carrying that state from chunk to chunk, is shared by all of them in
+//! [`classify`], and [`scan`] drives any of them over a whole input.
+
+use crate::utf8::{self, Utf8State};
+use crate::ScanState;
+
+/// Character classes by low nibble, see [`shuffle_lookup`](crate::shuffle_lookup).
+/// A byte is in a class when both its nibbles are.
+#[cfg(target_arch = "x86_64")]
+const LOW_NIBBLES: [u8; 16] = [
+    /* 0 */ 0x01 | 0x10, // " " | "\0"
+    /* 1 */ 0x00,
+    /* 2 */ 0x40, // "\""
+    /* 3 */ 0x00,
+    /* 4 */ 0x00,
+    /* 5 */ 0x00,
+    /* 6 */ 0x00,
+    /* 7 */ 0x00,
+    /* 8 */ 0x00,
+    /* 9 */ 0x00,
+    /* a */ 0x20, // "\n"
+    /* b */ 0x00,
+    /* c */ 0x02 | 0x80, // "," | "\\"
+    /* d */ 0x04, // "="
+    /* e */ 0x00,
+    /* f */ 0x00,
+];
+
+/// Character classes by high nibble.
+#[cfg(target_arch = "x86_64")]
+const HIGH_NIBBLES: [u8; 16] = [
+    /* 0 */ 0x10 | 0x20, // "\0" | "\n"
+    /* 1 */ 0x00,
+    /* 2 */ 0x01 | 0x02 | 0x40, // " " | "," | "\""
+    /* 3 */ 0x04, // "="
+    /* 4 */ 0x00,
+    /* 5 */ 0x80, // "\\"
+    /* 6 */ 0x00,
+    /* 7 */ 0x00,
+    /* 8 */ 0x00,
+    /* 9 */ 0x00,
+    /* a */ 0x00,
+    /* b */ 0x00,
+    /* c */ 0x00,
+    /* d */ 0x00,
+    /* e */ 0x00,
+    /* f */ 0x00,
+];
+
+/// The classes with a bit of their own in [`Masks`].
+#[cfg(target_arch = "x86_64")]
+const STRUCTURAL: u8 = 0x3F;
+#[cfg(target_arch = "x86_64")]
+const SPACE: u8 = 0x01;
+#[cfg(target_arch = "x86_64")]
+const EQUALS: u8 = 0x04;
+#[cfg(target_arch = "x86_64")]
+const LINE_END: u8 = 0x10 | 0x20;
+#[cfg(target_arch = "x86_64")]
+const QUOTE: u8 = 0x40;
+#[cfg(target_arch = "x86_64")]
+const BACKSLASH: u8 = 0x80;
+
+/// Raw classes of the bytes of one chunk, bit `i` standing for byte `i`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Masks {
+    pub(crate) structural: u64,
+    pub(crate) quotes: u64,
+    pub(crate) backslashes: u64,
+    // The structural bytes that decide where a string may start
+    pub(crate) spaces: u64,
+    pub(crate) equals: u64,
+    pub(crate) line_ends: u64,
+}
+
+/// Classifies `WIDTH` bytes at a time.
+pub(crate) trait StructuralScanner {
+    /// At most 64, so that one bit per byte fits a `u64`.
+    const WIDTH: usize;
+
+    /// # Safety
+    ///
+    /// `chunk` must be exactly `WIDTH` bytes long and the CPU must support
+    /// whatever instructions the scanner uses.
+    unsafe fn masks(chunk: &[u8]) -> Masks;
+
+    /// Check a 64 byte block for valid UTF-8, see [`utf8`].
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support whatever instructions the scanner uses.
+    #[inline(always)]
+    unsafe fn check_utf8(block: &[u8], state: &mut Utf8State) {
+        utf8::check_block_scalar(block, state)
+    }
+}
+
+/// Turn the quote positions of a `width` byte chunk into a mask of the bytes
+/// that lie inside a quoted string, from the opening quote up to but not
+/// including the closing one.
+///
+/// Only a string field value is quoted, so a quote only opens a string right
+/// after an `=` in the field set, i.e. once the line had a space. Anywhere
+/// else it is a plain byte of a measurement, key or tag value. Inside a
+/// string, every quote closes it. `masks` must have escaped bytes removed.
+///
+/// Most chunks have no quotes and do not start inside a string, and only need
+/// to track whether the line is in its field set yet. The rest walk their
+/// quotes, spaces and line ends in order.
+#[inline(always)]
+fn string_mask(masks: &Masks, state: &mut ScanState, width: usize) -> u64 {
+    let top = width - 1;
+    if masks.quotes == 0 && state.prev_in_string == 0 {
+        state.prev_in_fields = match masks.line_ends {
+            0 => state.prev_in_fields | (masks.spaces != 0) as u64,
+            ends => {
+                // A space after the last line end starts the field set
+                let last_end = 63 - ends.leading_zeros();
+                (masks.spaces >> last_end >> 1 != 0) as u64
+            }
+        };
+        state.prev_equals = (masks.equals >> top) & 1;
+        return 0;
+    }
+
+    let after_equals = masks.equals << 1 | state.prev_equals;
+    let mut in_string = state.prev_in_string != 0;
+    let mut in_fields = state.prev_in_fields != 0;
+    let mut open: u64 = 1;
+    let mut mask = 0;
+    let mut events = masks.quotes | masks.spaces | masks.line_ends;
+    while events != 0 {
+        let bit = events & events.wrapping_neg();
+        events ^= bit;
+        if in_string {
+            if masks.quotes & bit != 0 {
+                mask |= bit - open;
+                in_string = false;
+            }
+        } else if masks.quotes & bit != 0 {
+            if in_fields && after_equals & bit != 0 {
+                open = bit;
+                in_string = true;
+            }
+        } else {
+            in_fields = masks.spaces & bit != 0;
+        }
+    }
+    if in_string {
+        mask |= !(open - 1);
+    }
+
+    state.prev_in_string = 0u64.wrapping_sub(in_string as u64);
+    state.prev_in_fields = in_fields as u64;
+    state.prev_equals = (masks.equals & !mask) >> top & 1;
+    mask
+}
+
+/// Find the bytes escaped by a backslash in a `width` byte chunk, simdjson
+/// style: a byte is escaped when it follows an odd-length run of backslashes.
+/// Escaped bytes are never structural and never open or close a string.
+///
+/// `prev_escaped` is 1 when the first byte of this chunk is escaped by a run
+/// ending the previous chunk, and is updated for the next chunk.
+#[inline(always)]
+fn escaped_mask(backslash_bits: u64, prev_escaped: &mut u64, width: usize) -> u64 {
+    const EVEN_BITS: u64 = 0x5555_5555_5555_5555;
+    debug_assert!(width <= 64);
+
+    // An escaped backslash does not start a new run
+    let backslash = backslash_bits & !*prev_escaped;
+    let follows_escape = backslash << 1 | *prev_escaped;
+    let odd_sequence_starts = backslash & !EVEN_BITS & !follows_escape;
+    let (sequences_starting_on_even_bits, overflow) =
+        odd_sequence_starts.overflowing_add(backslash);
+    let invert_mask = sequences_starting_on_even_bits << 1;
+    let escaped = (EVEN_BITS ^ invert_mask) & follows_escape;
+
+    // A full chunk carries out of the top bit, a narrower one into the bit
+    // right above it
+    *prev_escaped = if width == 64 {
+        overflow as u64
+    } else {
+        (escaped >> width) & 1
+    };
+    escaped
+}
+
+/// Classify one chunk of `S::WIDTH` bytes, returning a bitmask of its
+/// structural bytes outside quoted strings and not escaped.
+///
+/// # Safety
+///
+/// See [`StructuralScanner::masks`].
+#[inline(always)]
+pub(crate) unsafe fn classify<S: StructuralScanner>(chunk: &[u8], state: &mut ScanState) -> u64 {
+    let masks = S::masks(chunk);
+    let escaped = escaped_mask(masks.backslashes, &mut state.prev_escaped, S::WIDTH);
+    let in_string = string_mask(&unescaped(masks, escaped), state, S::WIDTH);
+    masks.structural & !in_string & !escaped
+}
+
+/// `masks` without the `escaped` bytes, which are plain bytes whatever they
+/// are.
+#[inline(always)]
+fn unescaped(masks: Masks, escaped: u64) -> Masks {
+    Masks {
+        structural: masks.structural & !escaped,
+        quotes: masks.quotes & !escaped,
+        backslashes: masks.backslashes,
+        spaces: masks.spaces & !escaped,
+        equals: masks.equals & !escaped,
+        line_ends: masks.line_ends & !escaped,
+    }
+}
+
+/// Bytes per block in [`lookup`], one bit each in a `u64`.
+const BLOCK_SIZE: usize = 64;
+
+/// Like [`classify`], but for a whole 64 byte block, `S::WIDTH` bytes at a
+/// time. The string and escape masks are worked out once for the block.
+///
+/// # Safety
+///
+/// `block` must be exactly 64 bytes long, and the CPU must support the
+/// instructions `S` uses.
+#[inline(always)]
+unsafe fn classify_block<S: StructuralScanner>(block: &[u8], state: &mut ScanState) -> u64 {
+    let mut masks = Masks::default();
+    for (idx, chunk) in block.chunks_exact(S::WIDTH).enumerate() {
+        let chunk_masks = S::masks(chunk);
+        let shift = idx * S::WIDTH;
+        masks.structural |= chunk_masks.structural << shift;
+        masks.quotes |= chunk_masks.quotes << shift;
+        masks.backslashes |= chunk_masks.backslashes << shift;
+        masks.spaces |= chunk_masks.spaces << shift;
+        masks.equals |= chunk_masks.equals << shift;
+        masks.line_ends |= chunk_masks.line_ends << shift;
+    }
+
+    let escaped = escaped_mask(masks.backslashes, &mut state.prev_escaped, BLOCK_SIZE);
+    let in_string = string_mask(&unescaped(masks, escaped), state, BLOCK_SIZE);
+    masks.structural & !in_string & !escaped
+}
+
+/// Longest input whose offsets fit a `u32`, end of input included. Longer
+/// inputs are scanned in segments of this many bytes, with offsets relative to
+/// the start of the segment. It is a whole number of blocks, so the scan state
+/// carries from one segment to the next as from one block to the next.
+pub(crate) const SEGMENT_SIZE: usize = u32::MAX as usize & !(BLOCK_SIZE - 1);
+
+/// Input bytes to expect per structural offset, which is about what generated
+/// lines have. A good guess saves reallocating the index as it grows.
+const BYTES_PER_OFFSET: usize = 4;
+
+/// Collect the offsets of all structural bytes of `record`, see [`scan`].
+///
+/// # Panics
+///
+/// Panics when `record` is longer than [`SEGMENT_SIZE`].
+///
+/// # Safety
+///
+/// The CPU must support the instructions `S` uses.
+#[inline(always)]
+pub(crate) unsafe fn lookup<S: StructuralScanner>(record: &str) -> Vec<u32> {
+    assert!(
+        record.len() <= SEGMENT_SIZE,
+        "inputs over 4 GiB do not fit u32 offsets, use Parser::parse"
+    );
+    let mut index = Vec::new();
+    scan::<S, false>(record.as_bytes(), &mut ScanState::default(), &mut index);
+    index
+}
+
+/// Append the offsets of all structural bytes of `bytes` to `index`, 64 bytes
+/// at a time, starting from `state` and leaving it for whatever follows. When
+/// `bytes` does not end on a block boundary, its end is reported as well, as
+/// the first byte of the zero padding of the last block.
+///
+/// With `CHECK`, the same pass validates UTF-8 into `state.utf8`. `bytes` is
+/// then the whole input, since a sequence cut off at its end is an error.
+///
+/// # Safety
+///
+/// `bytes` must be at most [`SEGMENT_SIZE`] long, and the CPU must support the
+/// instructions `S` uses.
+#[inline(always)]
+pub(crate) unsafe fn scan<S: StructuralScanner, const CHECK: bool>(
+    bytes: &[u8],
+    state: &mut ScanState,
+    index: &mut Vec<u32>,
+) {
+    let len = bytes.len();
+    debug_assert!(len <= SEGMENT_SIZE);
+    index.reserve(len / BYTES_PER_OFFSET + BLOCK_SIZE);
+
+    // Full blocks are classified in place, only the last partial one is
+    // copied to pad it
+    let mut idx: usize = 0;
+    for block in bytes.chunks_exact(BLOCK_SIZE) {
+        let bits = classify_block::<S>(block, state);
+        if CHECK {
+            S::check_utf8(block, &mut state.utf8);
+        }
+        flatten(index, bits, idx as u32);
+        idx += BLOCK_SIZE;
+    }
+
+    if idx < len {
+        let mut block = [0u8; BLOCK_SIZE];
+        block[..len - idx].copy_from_slice(&bytes[idx..]);
+
+        // Keep the first padding byte, it reports the end of the input
+        let keep = len - idx + 1;
+        let keep_mask = if keep >= BLOCK_SIZE {
+            u64::MAX
+        } else {
+            (1u64 << keep) - 1
+        };
+        let bits = classify_block::<S>(&block, state) & keep_mask;
+        if CHECK {
+            S::check_utf8(&block, &mut state.utf8);
+        }
+        flatten(index, bits, idx as u32);
+    } else if CHECK {
+        // Zeros after the input show a sequence cut off at its end, just like
+        // the padding of a partial block does
+        S::check_utf8(&[0; BLOCK_SIZE], &mut state.utf8);
+    }
+}
+
+/// [`scan`] with the SSE4.1 scanner.
+///
+/// # Safety
+///
+/// See [`scan`].
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn scan_sse41<const CHECK: bool>(
+    bytes: &[u8],
+    state: &mut ScanState,
+    index: &mut Vec<u32>,
+) {
+    scan::<Sse41, CHECK>(bytes, state, index)
+}
+
+/// [`scan`] with the AVX2 scanner.
+///
+/// # Safety
+///
+/// See [`scan`].
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn scan_avx2<const CHECK: bool>(
+    bytes: &[u8],
+    state: &mut ScanState,
+    index: &mut Vec<u32>,
+) {
+    scan::<Avx2, CHECK>(bytes, state, index)
+}
+
+/// [`scan`] with the AVX-512 scanner.
+///
+/// # Safety
+///
+/// See [`scan`].
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512bw")]
+pub(crate) unsafe fn scan_avx512<const CHECK: bool>(
+    bytes: &[u8],
+    state: &mut ScanState,
+    index: &mut Vec<u32>,
+) {
+    scan::<Avx512, CHECK>(bytes, state, index)
+}
+
+/// Append the positions of the set bits of `bits` to `res_vec`, simdjson
+/// style: write 8 offsets at a time without checking how many bits are left,
+/// and fix the length up afterwards. Most blocks have few structural bytes, so
+/// the first round is usually the only one and there is no branch per bit.
+#[inline(always)]
+fn flatten(res_vec: &mut Vec<u32>, mut bits: u64, idx: u32) {
+    let count = bits.count_ones() as usize;
+    res_vec.reserve(BLOCK_SIZE);
+    let start = res_vec.len();
+    // At most 64 offsets are written, which the reserve above made room for
+    let dst = unsafe { res_vec.as_mut_ptr().add(start) };
+
+    let mut written = 0;
+    loop {
+        // Past the last set bit trailing_zeros() is 64, the garbage written
+        // there is cut off below
+        for slot in written..written + 8 {
+            unsafe { dst.add(slot).write(idx + bits.trailing_zeros()) };
+            bits &= bits.wrapping_sub(1);
+        }
+        written += 8;
+        if written >= count {
+            break;
+        }
+    }
+
+    unsafe { res_vec.set_len(start + count) };
+}
+
+/// Position of the first newline in `bytes`, 16 bytes at a time.
+pub(crate) fn find_newline(bytes: &[u8]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::*;
+
+        // SSE2 is part of x86_64, no need to check for it
+        let mut chunks = bytes.chunks_exact(16);
+        for (idx, chunk) in (&mut chunks).enumerate() {
+            let newlines = unsafe {
+                let chunk = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+                _mm_movemask_epi8(_mm_cmpeq_epi8(chunk, _mm_set1_epi8(b'\n' as i8)))
+            };
+            if newlines != 0 {
+                return Some(idx * 16 + newlines.trailing_zeros() as usize);
+            }
+        }
+        let tail = bytes.len() - chunks.remainder().len();
+        chunks
+            .remainder()
+            .iter()
+            .position(|&c| c == b'\n')
+            .map(|idx| tail + idx)
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    bytes.iter().position(|&c| c == b'\n')
+}
+
+/// One byte at a time, for any width up to 64.
+pub(crate) struct Scalar<const WIDTH: usize>;
+
+impl<const WIDTH: usize> StructuralScanner for Scalar<WIDTH> {
+    const WIDTH: usize = WIDTH;
+
+    #[inline(always)]
+    unsafe fn masks(chunk: &[u8]) -> Masks {
+        let mut masks = Masks::default();
+        for (idx, &c) in chunk.iter().enumerate() {
+            let bit = 1 << idx;
+            match c {
+                b' ' => masks.spaces |= bit,
+                b'=' => masks.equals |= bit,
+                b'\n' | 0x00 => masks.line_ends |= bit,
+                b'"' => masks.quotes |= bit,
+                b'\\' => masks.backslashes |= bit,
+                _ => {}
+            }
+            if matches!(c, b' ' | b',' | b'=' | b'\n' | 0x00) {
+                masks.structural |= bit;
+            }
+        }
+        masks
+    }
+}
+
+/// SIMD within a register: 8 bytes per `u64`, 64 bytes per chunk, on any CPU.
+pub(crate) struct Swar;
+
+impl Swar {
+    const ONES: u64 = 0x0101_0101_0101_0101;
+    const LOW_SEVEN: u64 = 0x7F7F_7F7F_7F7F_7F7F;
+
+    /// 0x80 in every byte of `word` that equals `byte`, 0x00 elsewhere.
+    #[inline(always)]
+    fn eq(word: u64, byte: u8) -> u64 {
+        let x = word ^ (Self::ONES * byte as u64);
+        !(((x & Self::LOW_SEVEN) + Self::LOW_SEVEN) | x | Self::LOW_SEVEN)
+    }
+
+    /// Gather the top bit of each byte into the low 8 bits.
+    #[inline(always)]
+    fn movemask(bytes: u64) -> u64 {
+        ((bytes >> 7).wrapping_mul(0x0102_0408_1020_4080)) >> 56
+    }
+}
+
+impl StructuralScanner for Swar {
+    const WIDTH: usize = 64;
+
+    #[inline(always)]
+    unsafe fn masks(chunk: &[u8]) -> Masks {
+        let mut masks = Masks::default();
+        for (idx, word) in chunk.chunks_exact(8).enumerate() {
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            let shift = idx * 8;
+            let spaces = Swar::eq(word, b' ');
+            let equals = Swar::eq(word, b'=');
+            let line_ends = Swar::eq(word, b'\n') | Swar::eq(word, 0x00);
+            let structural = spaces | equals | line_ends | Swar::eq(word, b',');
+            masks.structural |= Swar::movemask(structural) << shift;
+            masks.quotes |= Swar::movemask(Swar::eq(word, b'"')) << shift;
+            masks.backslashes |= Swar::movemask(Swar::eq(word, b'\\')) << shift;
+            masks.spaces |= Swar::movemask(spaces) << shift;
+            masks.equals |= Swar::movemask(equals) << shift;
+            masks.line_ends |= Swar::movemask(line_ends) << shift;
+        }
+        masks
+    }
+}
+
+/// `pshufb` nibble lookups on 16 bytes.
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct Sse41;
+
+#[cfg(target_arch = "x86_64")]
+impl StructuralScanner for Sse41 {
+    const WIDTH: usize = 16;
+
+    #[inline]
+    #[target_feature(enable = "sse4.1")]
+    unsafe fn masks(chunk: &[u8]) -> Masks {
+        use std::arch::x86_64::*;
+
+        let input = _mm_loadu_si128(chunk.as_ptr() as *const _);
+        let low_table = _mm_loadu_si128(LOW_NIBBLES.as_ptr() as *const _);
+        let high_table = _mm_loadu_si128(HIGH_NIBBLES.as_ptr() as *const _);
+
+        let lower_nibbles = _mm_and_si128(input, _mm_set1_epi8(0x0F));
+        let higher_nibbles = _mm_and_si128(_mm_srli_epi16(input, 4), _mm_set1_epi8(0x0F));
+        let intersection = _mm_and_si128(
+            _mm_shuffle_epi8(low_table, lower_nibbles),
+            _mm_shuffle_epi8(high_table, higher_nibbles),
+        );
+
+        let class = |bits: u8| {
+            let masked = _mm_and_si128(intersection, _mm_set1_epi8(bits as i8));
+            let none = _mm_cmpeq_epi8(masked, _mm_setzero_si128());
+            !(_mm_movemask_epi8(none) as u32 as u64) & 0xFFFF
+        };
+        Masks {
+            structural: class(STRUCTURAL),
+            quotes: class(QUOTE),
+            // Only the backslash class uses the top bit, so the movemask is
+            // enough
+            backslashes: _mm_movemask_epi8(intersection) as u32 as u64,
+            spaces: class(SPACE),
+            equals: class(EQUALS),
+            line_ends: class(LINE_END),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn check_utf8(block: &[u8], state: &mut Utf8State) {
+        utf8::check_block_sse41(block, state)
+    }
+}
+
+/// `vpshufb` nibble lookups on 32 bytes.
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct Avx2;
+
+#[cfg(target_arch = "x86_64")]
+impl StructuralScanner for Avx2 {
+    const WIDTH: usize = 32;
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn masks(chunk: &[u8]) -> Masks {
+        use std::arch::x86_64::*;
+
+        let input = _mm256_loadu_si256(chunk.as_ptr() as *const _);
+        // vpshufb looks up each 128 bit lane separately, so both lanes need
+        // the table
+        let low_table =
+            _mm256_broadcastsi128_si256(_mm_loadu_si128(LOW_NIBBLES.as_ptr() as *const _));
+        let high_table =
+            _mm256_broadcastsi128_si256(_mm_loadu_si128(HIGH_NIBBLES.as_ptr() as *const _));
+
+        let lower_nibbles = _mm256_and_si256(input, _mm256_set1_epi8(0x0F));
+        let higher_nibbles = _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0x0F));
+        let intersection = _mm256_and_si256(
+            _mm256_shuffle_epi8(low_table, lower_nibbles),
+            _mm256_shuffle_epi8(high_table, higher_nibbles),
+        );
+
+        let class = |bits: u8| {
+            let masked = _mm256_and_si256(intersection, _mm256_set1_epi8(bits as i8));
+            let none = _mm256_cmpeq_epi8(masked, _mm256_setzero_si256());
+            !(_mm256_movemask_epi8(none) as u32) as u64
+        };
+        Masks {
+            structural: class(STRUCTURAL),
+            quotes: class(QUOTE),
+            backslashes: _mm256_movemask_epi8(intersection) as u32 as u64,
+            spaces: class(SPACE),
+            equals: class(EQUALS),
+            line_ends: class(LINE_END),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn check_utf8(block: &[u8], state: &mut Utf8State) {
+        utf8::check_block_sse41(block, state)
+    }
+}
+
+/// `vpshufb` nibble lookups on 64 bytes, with mask registers doing the
+/// movemasks.
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct Avx512;
+
+#[cfg(target_arch = "x86_64")]
+impl StructuralScanner for Avx512 {
+    const WIDTH: usize = 64;
+
+    #[inline]
+    #[target_feature(enable = "avx512bw")]
+    unsafe fn masks(chunk: &[u8]) -> Masks {
+        use std::arch::x86_64::*;
+
+        let input = _mm512_loadu_si512(chunk.as_ptr() as *const _);
+        let low_table = _mm512_broadcast_i32x4(_mm_loadu_si128(LOW_NIBBLES.as_ptr() as *const _));
+        let high_table = _mm512_broadcast_i32x4(_mm_loadu_si128(HIGH_NIBBLES.as_ptr() as *const _));
+
+        let lower_nibbles = _mm512_and_si512(input, _mm512_set1_epi8(0x0F));
+        let higher_nibbles = _mm512_and_si512(_mm512_srli_epi16(input, 4), _mm512_set1_epi8(0x0F));
+        let intersection = _mm512_and_si512(
+            _mm512_shuffle_epi8(low_table, lower_nibbles),
+            _mm512_shuffle_epi8(high_table, higher_nibbles),
+        );
+
+        let class = |bits: u8| _mm512_test_epi8_mask(intersection, _mm512_set1_epi8(bits as i8));
+        Masks {
+            structural: class(STRUCTURAL),
+            quotes: class(QUOTE),
+            backslashes: class(BACKSLASH),
+            spaces: class(SPACE),
+            equals: class(EQUALS),
+            line_ends: class(LINE_END),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn check_utf8(block: &[u8], state: &mut Utf8State) {
+        utf8::check_block_sse41(block, state)
+    }
+}
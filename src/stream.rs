@@ -0,0 +1,343 @@
+use std::io::{self, Read};
+
+use crate::backend::{active_classifier, ClassifyFn};
+use crate::{build_tape_indexed, Parser, Points, ScanState, StreamError};
+
+const SIMD_LENGTH: usize = 16;
+const DEFAULT_READ_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_LINE_LEN: usize = 1024 * 1024;
+
+/// Parses line protocol from a reader, such as a socket or a pipe.
+///
+/// Reads go into an internal buffer which is classified 16 bytes at a time as
+/// it fills, so newlines inside quoted strings do not end a line, and
+/// [`next_points`](Self::next_points) parses straight from that
+/// classification. Every call hands out all complete lines buffered so far;
+/// a trailing partial line is kept for the next read.
+///
+/// ```
+/// use influx_parser::StreamParser;
+///
+/// let input: &[u8] = b"cpu usage=0.5\nmem free=7i\n";
+/// let mut stream = StreamParser::new(input);
+/// while let Some(points) = stream.next_points().unwrap() {
+///     for point in points.iter() {
+///         println!("{}", point.measurement);
+///     }
+/// }
+/// ```
+pub struct StreamParser<R> {
+    reader: R,
+    lines: LineBuffer,
+}
+
+impl<R: Read> StreamParser<R> {
+    pub fn new(reader: R) -> Self {
+        StreamParser {
+            reader,
+            lines: LineBuffer::new(),
+        }
+    }
+
+    /// How many bytes to ask the reader for at a time, 64 KiB by default.
+    pub fn read_size(mut self, read_size: usize) -> Self {
+        self.lines.read_size = read_size.max(1);
+        self
+    }
+
+    /// The longest line that is buffered before giving up with
+    /// [`StreamError::LineTooLong`], 1 MiB by default.
+    pub fn max_line_len(mut self, max_line_len: usize) -> Self {
+        self.lines.max_line_len = max_line_len;
+        self
+    }
+
+    /// All complete lines read so far, or `None` once the reader is
+    /// exhausted. The last line does not need a trailing newline.
+    pub fn next_lines(&mut self) -> Result<Option<&str>, StreamError> {
+        self.lines.release();
+        self.fill()?;
+        self.lines.batch()
+    }
+
+    /// Parse the next batch of complete lines. Error positions are relative to
+    /// the whole stream.
+    pub fn next_points(&mut self) -> Result<Option<Points<'_>>, StreamError> {
+        // The batch starts at the same stream position however the buffer is
+        // compacted while filling it
+        self.lines.release();
+        let (offset, lines) = self.lines.position();
+        self.fill()?;
+        let Some((batch, index)) = self.lines.indexed_batch()? else {
+            return Ok(None);
+        };
+        match build_tape_indexed(batch, index, &Parser::new()) {
+            Ok(parsed) => Ok(Some(Points::from_tape(parsed.nodes))),
+            Err(mut err) => {
+                err.offset += offset;
+                err.line += lines;
+                Err(StreamError::Parse(err))
+            }
+        }
+    }
+
+    /// Read until there is a batch to hand out.
+    fn fill(&mut self) -> Result<(), StreamError> {
+        while self.lines.needs_input()? {
+            let read = loop {
+                match self.reader.read(self.lines.spare()) {
+                    Ok(read) => break read,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            };
+            self.lines.filled(read);
+        }
+        Ok(())
+    }
+}
+
+/// The buffer behind the stream parsers, which only hands out complete lines.
+/// It does no I/O itself: the caller reads into [`spare`](Self::spare)
+/// whenever [`needs_input`](Self::needs_input) says so.
+pub(crate) struct LineBuffer {
+    buf: Vec<u8>,
+    // Start of the lines not handed out yet
+    start: usize,
+    // End of the bytes read into `buf`
+    filled: usize,
+    // End of the bytes run through the scanner
+    scanned: usize,
+    // Structural offsets into `buf` of the bytes up to `scanned`, from the
+    // lines not handed out yet on
+    index: Vec<u32>,
+    // Structural bits of the partial chunk at `scanned`
+    tail: u64,
+    // The index of the batch handed out last, relative to its start
+    batch_index: Vec<u32>,
+    // End of the last complete line, just past its newline
+    line_end: usize,
+    state: ScanState,
+    classify: ClassifyFn,
+    pub(crate) read_size: usize,
+    pub(crate) max_line_len: usize,
+    eof: bool,
+    // Position of `buf[0]` in the stream, and the newlines before it
+    stream_offset: u64,
+    stream_lines: usize,
+    // The batch handed out last, released on the next call
+    handed_out: usize,
+}
+
+impl LineBuffer {
+    pub(crate) fn new() -> Self {
+        LineBuffer {
+            buf: Vec::new(),
+            start: 0,
+            filled: 0,
+            scanned: 0,
+            index: Vec::new(),
+            tail: 0,
+            batch_index: Vec::new(),
+            line_end: 0,
+            state: ScanState::default(),
+            classify: active_classifier(),
+            read_size: DEFAULT_READ_SIZE,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            eof: false,
+            stream_offset: 0,
+            stream_lines: 0,
+            handed_out: 0,
+        }
+    }
+
+    /// Whether another read is needed before [`batch`](Self::batch) has
+    /// anything to hand out.
+    pub(crate) fn needs_input(&self) -> Result<bool, StreamError> {
+        if self.line_end > self.start || self.eof {
+            return Ok(false);
+        }
+        if self.filled - self.start > self.max_line_len {
+            return Err(StreamError::LineTooLong {
+                offset: self.stream_offset + self.start as u64,
+                max_line_len: self.max_line_len,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Room for the next read, after moving the partial line to the front of
+    /// the buffer. Lines handed out from a partial chunk stay until it is
+    /// complete and classified for good.
+    pub(crate) fn spare(&mut self) -> &mut [u8] {
+        let keep = self.start.min(self.scanned);
+        if keep > 0 {
+            // Drop the offsets of the lines handed out, and move the rest
+            // along with their bytes
+            let handed_out = self
+                .index
+                .partition_point(|&offset| (offset as usize) < self.start);
+            self.index.drain(..handed_out);
+            for offset in &mut self.index {
+                *offset -= keep as u32;
+            }
+
+            self.buf.copy_within(keep..self.filled, 0);
+            self.stream_offset += keep as u64;
+            self.filled -= keep;
+            self.scanned -= keep;
+            self.line_end = self.line_end.saturating_sub(keep);
+            self.start -= keep;
+        }
+
+        if self.buf.len() < self.filled + self.read_size {
+            self.buf.resize(self.filled + self.read_size, 0);
+            // The index holds `u32` offsets into the buffer
+            assert!(
+                self.buf.len() <= u32::MAX as usize,
+                "line buffer over 4 GiB"
+            );
+        }
+        &mut self.buf[self.filled..]
+    }
+
+    /// Account for `read` bytes read into [`spare`](Self::spare), none
+    /// meaning the end of the input, and classify them.
+    pub(crate) fn filled(&mut self, read: usize) {
+        self.filled += read;
+        self.eof = read == 0;
+        self.scan();
+    }
+
+    /// All complete lines buffered, or `None` once everything was handed out
+    /// and the input is exhausted. Only call this once
+    /// [`needs_input`](Self::needs_input) says no more reads are needed.
+    pub(crate) fn batch(&mut self) -> Result<Option<&str>, StreamError> {
+        Ok(self.cut()?.then(|| self.handed_out()))
+    }
+
+    /// Like [`batch`](Self::batch), together with the structural offsets of
+    /// the batch, as stage 2 takes them.
+    pub(crate) fn indexed_batch(&mut self) -> Result<Option<(&str, &[u32])>, StreamError> {
+        if !self.cut()? {
+            return Ok(None);
+        }
+
+        let (start, end) = (self.start, self.start + self.handed_out);
+        let from = self
+            .index
+            .partition_point(|&offset| (offset as usize) < start);
+        let to = self
+            .index
+            .partition_point(|&offset| (offset as usize) < end);
+        self.batch_index.clear();
+        self.batch_index.extend(
+            self.index[from..to]
+                .iter()
+                .map(|&offset| offset - start as u32),
+        );
+        // The batch may end in the partial chunk
+        let mut tail = self.tail;
+        while tail != 0 {
+            let offset = self.scanned + tail.trailing_zeros() as usize;
+            if (start..end).contains(&offset) {
+                self.batch_index.push((offset - start) as u32);
+            }
+            tail &= tail - 1;
+        }
+
+        Ok(Some((self.handed_out(), &self.batch_index)))
+    }
+
+    /// Pick the next batch, if there is one.
+    fn cut(&mut self) -> Result<bool, StreamError> {
+        if self.line_end <= self.start {
+            debug_assert!(self.eof);
+            if self.start == self.filled {
+                return Ok(false);
+            }
+            self.line_end = self.filled;
+        }
+
+        let batch = &self.buf[self.start..self.line_end];
+        self.handed_out = match std::str::from_utf8(batch) {
+            Ok(_) => batch.len(),
+            // The lines before the invalid one go out first
+            Err(err) => match batch[..err.valid_up_to()].iter().rposition(|&c| c == b'\n') {
+                Some(pos) => pos + 1,
+                None => {
+                    return Err(StreamError::InvalidUtf8 {
+                        offset: self.stream_offset + (self.start + err.valid_up_to()) as u64,
+                    })
+                }
+            },
+        };
+        Ok(true)
+    }
+
+    /// The batch picked by [`cut`](Self::cut).
+    fn handed_out(&self) -> &str {
+        let batch = &self.buf[self.start..self.start + self.handed_out];
+        // SAFETY: `cut` found it valid, either the whole batch or up to just
+        // past a newline before the first invalid byte
+        unsafe { std::str::from_utf8_unchecked(batch) }
+    }
+
+    /// Byte offset and number of newlines in the stream before the next
+    /// batch.
+    pub(crate) fn position(&self) -> (usize, usize) {
+        (
+            (self.stream_offset + self.start as u64) as usize,
+            self.stream_lines,
+        )
+    }
+
+    /// Drop the batch handed out by the previous call.
+    pub(crate) fn release(&mut self) {
+        let batch = &self.buf[self.start..self.start + self.handed_out];
+        self.stream_lines += batch.iter().filter(|&&c| c == b'\n').count();
+        self.start += self.handed_out;
+        self.handed_out = 0;
+    }
+
+    /// Classify the newly read bytes and remember where the last line ends.
+    /// A partial chunk is classified against a copy of the scanner state,
+    /// since the state cannot be carried over from its zero padding, and
+    /// again once it is complete. Its newlines count right away though, or
+    /// a short line would wait for 16 more bytes that may never come.
+    fn scan(&mut self) {
+        self.tail = 0;
+        while self.scanned < self.filled {
+            let valid = (self.filled - self.scanned).min(SIMD_LENGTH);
+            let partial = valid < SIMD_LENGTH && !self.eof;
+
+            let mut chunk: [u8; SIMD_LENGTH] = [0x00; SIMD_LENGTH];
+            chunk[..valid].copy_from_slice(&self.buf[self.scanned..self.scanned + valid]);
+            let mut tail_state = self.state;
+            let state = if partial {
+                &mut tail_state
+            } else {
+                &mut self.state
+            };
+            let bits = (self.classify)(&chunk, state) & ((1u64 << valid) - 1);
+
+            let mut rest = bits;
+            while rest != 0 {
+                let pos = rest.trailing_zeros() as usize;
+                if chunk[pos] == b'\n' {
+                    self.line_end = self.scanned + pos + 1;
+                }
+                if !partial {
+                    self.index.push((self.scanned + pos) as u32);
+                }
+                rest &= rest - 1;
+            }
+
+            if partial {
+                self.tail = bits;
+                break;
+            }
+            self.scanned += valid;
+        }
+    }
+}
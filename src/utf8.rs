@@ -0,0 +1,209 @@
+//! UTF-8 validation for byte input, run by stage 1 on the blocks it
+//! classifies.
+//!
+//! This is the lookup algorithm of Keiser and Lemire, "Validating UTF-8 In
+//! Less Than One Instruction Per Byte". Every byte is checked against the one
+//! before it with three nibble lookups, each table giving the errors that
+//! nibble allows; an error is a bit set in all three. A second check makes
+//! sure the third and fourth bytes of long sequences are continuations. Only
+//! the last three bytes of a block matter for the next one, so blocks can be
+//! checked one at a time.
+//!
+//! The check only says whether the input is valid. Where it is not is left to
+//! [`std::str::from_utf8`], as invalid input is the rare case.
+
+// Error classes, one bit each
+const TOO_SHORT: u8 = 1 << 0;
+const TOO_LONG: u8 = 1 << 1;
+const OVERLONG_3: u8 = 1 << 2;
+const TOO_LARGE: u8 = 1 << 3;
+const SURROGATE: u8 = 1 << 4;
+const OVERLONG_2: u8 = 1 << 5;
+const TOO_LARGE_1000: u8 = 1 << 6;
+const OVERLONG_4: u8 = 1 << 6;
+const TWO_CONTS: u8 = 1 << 7;
+const CARRY: u8 = TOO_SHORT | TOO_LONG | TWO_CONTS;
+
+/// Errors possible after the high nibble of the previous byte.
+const PREV_HIGH: [u8; 16] = [
+    // 0_______ ASCII
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    TOO_LONG,
+    // 10______ continuation
+    TWO_CONTS,
+    TWO_CONTS,
+    TWO_CONTS,
+    TWO_CONTS,
+    // 1100____ two byte lead
+    TOO_SHORT | OVERLONG_2,
+    // 1101____ two byte lead
+    TOO_SHORT,
+    // 1110____ three byte lead
+    TOO_SHORT | OVERLONG_3 | SURROGATE,
+    // 1111____ four byte lead
+    TOO_SHORT | TOO_LARGE | TOO_LARGE_1000 | OVERLONG_4,
+];
+
+/// Errors possible after the low nibble of the previous byte.
+const PREV_LOW: [u8; 16] = [
+    // ____0000
+    CARRY | OVERLONG_3 | OVERLONG_2 | OVERLONG_4,
+    // ____0001
+    CARRY | OVERLONG_2,
+    // ____001_
+    CARRY,
+    CARRY,
+    // ____0100
+    CARRY | TOO_LARGE,
+    // ____0101 and up
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    // ____1101
+    CARRY | TOO_LARGE | TOO_LARGE_1000 | SURROGATE,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+    CARRY | TOO_LARGE | TOO_LARGE_1000,
+];
+
+/// Errors possible given the high nibble of the byte itself.
+const HIGH: [u8; 16] = [
+    // 0_______ ASCII
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    // 1000____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE_1000 | OVERLONG_4,
+    // 1001____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE,
+    // 101_____
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+    TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+    // 11______ lead
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+    TOO_SHORT,
+];
+
+/// Validation state carried from one block to the next.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Utf8State {
+    // Last 16 bytes of the previous block
+    prev: [u8; 16],
+    error: bool,
+}
+
+impl Utf8State {
+    pub(crate) fn is_valid(&self) -> bool {
+        !self.error
+    }
+
+    /// Whether the last block ended in the middle of a sequence.
+    fn incomplete(&self) -> bool {
+        self.prev[15] >= 0xC0 || self.prev[14] >= 0xE0 || self.prev[13] >= 0xF0
+    }
+}
+
+/// Check one 64 byte block a byte at a time, with the same tables as the
+/// SIMD version.
+pub(crate) fn check_block_scalar(block: &[u8], state: &mut Utf8State) {
+    if block.is_ascii() {
+        state.error |= state.incomplete();
+    } else {
+        // The bytes 1, 2 and 3 places back, reaching into the previous block
+        let back = |idx: usize, by: usize| match idx.checked_sub(by) {
+            Some(idx) => block[idx],
+            None => state.prev[16 + idx - by],
+        };
+        let mut error = 0;
+        for (idx, &byte) in block.iter().enumerate() {
+            let prev1 = back(idx, 1);
+            let special = PREV_HIGH[(prev1 >> 4) as usize]
+                & PREV_LOW[(prev1 & 0x0F) as usize]
+                & HIGH[(byte >> 4) as usize];
+            // Only set in the top bit for third and fourth bytes
+            let must_continue = (back(idx, 2).saturating_sub(0xE0 - 0x80)
+                | back(idx, 3).saturating_sub(0xF0 - 0x80))
+                & 0x80;
+            error |= must_continue ^ special;
+        }
+        state.error |= error != 0;
+    }
+    state.prev.copy_from_slice(&block[block.len() - 16..]);
+}
+
+/// Check one 64 byte block 16 bytes at a time.
+///
+/// # Safety
+///
+/// The CPU must support SSE4.1.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn check_block_sse41(block: &[u8], state: &mut Utf8State) {
+    use std::arch::x86_64::*;
+
+    let load = |bytes: &[u8]| _mm_loadu_si128(bytes.as_ptr() as *const __m128i);
+    let lanes = [
+        load(&block[..16]),
+        load(&block[16..32]),
+        load(&block[32..48]),
+        load(&block[48..]),
+    ];
+    let any = _mm_or_si128(
+        _mm_or_si128(lanes[0], lanes[1]),
+        _mm_or_si128(lanes[2], lanes[3]),
+    );
+
+    if _mm_movemask_epi8(any) == 0 {
+        state.error |= state.incomplete();
+    } else {
+        let prev_high = load(&PREV_HIGH);
+        let prev_low = load(&PREV_LOW);
+        let high = load(&HIGH);
+        let nibble = _mm_set1_epi8(0x0F);
+
+        let mut prev = load(&state.prev);
+        let mut error = _mm_setzero_si128();
+        for input in lanes {
+            let prev1 = _mm_alignr_epi8::<15>(input, prev);
+            let special = _mm_and_si128(
+                _mm_and_si128(
+                    _mm_shuffle_epi8(prev_high, _mm_and_si128(_mm_srli_epi16(prev1, 4), nibble)),
+                    _mm_shuffle_epi8(prev_low, _mm_and_si128(prev1, nibble)),
+                ),
+                _mm_shuffle_epi8(high, _mm_and_si128(_mm_srli_epi16(input, 4), nibble)),
+            );
+
+            let prev2 = _mm_alignr_epi8::<14>(input, prev);
+            let prev3 = _mm_alignr_epi8::<13>(input, prev);
+            let must_continue = _mm_and_si128(
+                _mm_or_si128(
+                    _mm_subs_epu8(prev2, _mm_set1_epi8((0xE0u8 - 0x80) as i8)),
+                    _mm_subs_epu8(prev3, _mm_set1_epi8((0xF0u8 - 0x80) as i8)),
+                ),
+                _mm_set1_epi8(0x80u8 as i8),
+            );
+            error = _mm_or_si128(error, _mm_xor_si128(must_continue, special));
+            prev = input;
+        }
+        state.error |= _mm_testz_si128(error, error) == 0;
+    }
+    _mm_storeu_si128(state.prev.as_mut_ptr() as *mut __m128i, lanes[3]);
+}
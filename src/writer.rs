@@ -0,0 +1,204 @@
+//! Writing points back out as line protocol.
+
+use std::io::{self, Write};
+
+use crate::point::points_of;
+use crate::{FieldValue, Node, Point, Precision};
+
+/// Writes points as line protocol, one line each, to any [`io::Write`].
+///
+/// Measurements, keys and tag values are escaped, string field values are
+/// quoted and escaped, and integers get their `i` or `u` suffix, so what the
+/// parser reads back is the point that was written. Values the line protocol
+/// has no way to express, e.g. a NaN or a tag value with a newline, are an
+/// [`io::ErrorKind::InvalidInput`] error and nothing of their line is
+/// written.
+///
+/// Every line goes to the writer in a single `write_all`; wrap unbuffered
+/// writers in an [`io::BufWriter`].
+///
+/// ```
+/// use influx_parser::{parse_points, LineWriter};
+///
+/// let points = parse_points("cpu,host=a\\ b usage=0.5,msg=\"hi \\\"there\\\"\" 1000\n").unwrap();
+/// let mut writer = LineWriter::new(Vec::new());
+/// for point in points.iter() {
+///     writer.write_point(&point).unwrap();
+/// }
+/// assert_eq!(
+///     writer.into_inner(),
+///     b"cpu,host=a\\ b usage=0.5,msg=\"hi \\\"there\\\"\" 1000\n"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct LineWriter<W> {
+    out: W,
+    precision: Precision,
+    point_precision: Precision,
+    // The line being rendered, reused from one to the next
+    line: Vec<u8>,
+}
+
+impl<W: Write> LineWriter<W> {
+    pub fn new(out: W) -> Self {
+        LineWriter {
+            out,
+            precision: Precision::Nanoseconds,
+            point_precision: Precision::Nanoseconds,
+            line: Vec::new(),
+        }
+    }
+
+    /// The unit of the timestamps in the output, nanoseconds by default. Like
+    /// [`Parser::precision`](crate::Parser::precision), this has to match the
+    /// `precision` of the write the output is sent with.
+    ///
+    /// ```
+    /// use influx_parser::{parse_points, LineWriter, Precision};
+    ///
+    /// let points = parse_points("cpu f=1i 1500000000\n").unwrap();
+    /// let mut writer = LineWriter::new(Vec::new()).precision(Precision::Milliseconds);
+    /// writer.write_point(&points.get(0).unwrap()).unwrap();
+    /// assert_eq!(writer.into_inner(), b"cpu f=1i 1500\n");
+    /// ```
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// The unit of the timestamps of the points written, nanoseconds by
+    /// default. Points parsed with a
+    /// [`Parser::output_precision`](crate::Parser::output_precision) need the
+    /// same one here. Converting to a coarser [`precision`](Self::precision)
+    /// rounds towards negative infinity, see [`Precision::convert`].
+    pub fn point_precision(mut self, precision: Precision) -> Self {
+        self.point_precision = precision;
+        self
+    }
+
+    /// Write `point` as one line.
+    pub fn write_point(&mut self, point: &Point<'_>) -> io::Result<()> {
+        self.line.clear();
+        render(&mut self.line, point, self.point_precision, self.precision)?;
+        self.out.write_all(&self.line)
+    }
+
+    /// Write a tape as returned by the parser, one line per
+    /// [`Node::Measurement`].
+    pub fn write_nodes(&mut self, nodes: &[Node<'_>]) -> io::Result<()> {
+        for point in points_of(nodes) {
+            self.write_point(&point)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Render `point` and its newline into `line`.
+fn render(line: &mut Vec<u8>, point: &Point<'_>, from: Precision, to: Precision) -> io::Result<()> {
+    identifier(line, "measurement", point.measurement)?;
+    for tag in point.tags {
+        let Node::Tag { key, value } = tag else {
+            return Err(invalid("tags", "hold a node that is not a tag"));
+        };
+        line.push(b',');
+        identifier(line, "tag key", key)?;
+        line.push(b'=');
+        identifier(line, "tag value", value)?;
+    }
+
+    if point.fields.is_empty() {
+        return Err(invalid("a point", "has no fields"));
+    }
+    for (idx, field) in point.fields.iter().enumerate() {
+        let Node::Field { key, value } = field else {
+            return Err(invalid("fields", "hold a node that is not a field"));
+        };
+        line.push(if idx == 0 { b' ' } else { b',' });
+        identifier(line, "field key", key)?;
+        line.push(b'=');
+        field_value(line, value)?;
+    }
+
+    if let Some(timestamp) = point.timestamp {
+        let timestamp = from
+            .convert(timestamp, to)
+            .ok_or_else(|| invalid("timestamp", "is out of range in the output precision"))?;
+        write!(line, " {timestamp}")?;
+    }
+    line.push(b'\n');
+    Ok(())
+}
+
+/// Escape a measurement, key or tag value. The parser only unescapes `\,`,
+/// `\ ` and `\=` there and keeps any other backslash, while stage 1 takes
+/// every backslash to escape the byte after it. So an odd run of backslashes
+/// cannot be followed by a separator, and line ends cannot be escaped at all.
+/// Quotes need no escaping, they only open a string in a field value.
+fn identifier(line: &mut Vec<u8>, what: &str, text: &str) -> io::Result<()> {
+    if text.is_empty() {
+        return Err(invalid(what, "is empty"));
+    }
+
+    let mut backslashes = 0;
+    for &c in text.as_bytes() {
+        match c {
+            b',' | b' ' | b'=' if backslashes % 2 == 1 => {
+                return Err(invalid(
+                    what,
+                    "has an odd number of backslashes before a separator",
+                ))
+            }
+            b',' | b' ' | b'=' => line.push(b'\\'),
+            b'\n' | 0x00 => return Err(invalid(what, "has a newline or NUL byte")),
+            _ => {}
+        }
+        backslashes = if c == b'\\' { backslashes + 1 } else { 0 };
+        line.push(c);
+    }
+    if backslashes % 2 == 1 {
+        return Err(invalid(what, "ends in an odd number of backslashes"));
+    }
+    Ok(())
+}
+
+fn field_value(line: &mut Vec<u8>, value: &FieldValue<'_>) -> io::Result<()> {
+    match value {
+        FieldValue::Int(value) => write!(line, "{value}i"),
+        FieldValue::UInt(value) => write!(line, "{value}u"),
+        // Debug is the shortest text that reads back as the same float, and
+        // uses an exponent rather than hundreds of digits
+        FieldValue::Float(value) if value.is_finite() => write!(line, "{value:?}"),
+        FieldValue::Float(_) => Err(invalid("float field value", "is not finite")),
+        FieldValue::String(value) => {
+            line.push(b'"');
+            for &c in value.as_bytes() {
+                if c == b'"' || c == b'\\' {
+                    line.push(b'\\');
+                }
+                line.push(c);
+            }
+            line.push(b'"');
+            Ok(())
+        }
+        FieldValue::Bool(value) => write!(line, "{value}"),
+    }
+}
+
+fn invalid(what: &str, problem: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("cannot write line protocol: {what} {problem}"),
+    )
+}